@@ -0,0 +1,152 @@
+use std::{env, path::{Path, PathBuf}, time::Duration};
+
+use axum::http::HeaderValue;
+
+use mpc_service::off_chain::{network::{deadline::SessionTimeouts, registry::DEFAULT_DATA_DIR, setup::{NetworkOptions, Transport}}, vault::KeyEncryptionKey};
+
+/// Where the node keeps its key shares and how they are encrypted
+///
+/// `MPC_VAULT_DIR` defaults to `vault/party_{i}`. The key-encryption key is either
+/// `MPC_VAULT_KEK` (32 bytes, hex) or derived from `MPC_VAULT_PASSPHRASE`.
+pub struct VaultConfig {
+    pub dir: String,
+    pub kek: KeyEncryptionKey,
+}
+
+impl VaultConfig {
+    pub fn from_env(local_party_id: u16) -> Result<VaultConfig, String> {
+        let dir = env::var("MPC_VAULT_DIR").unwrap_or_else(|_| format!("vault/party_{}", local_party_id));
+
+        let kek = match (env::var("MPC_VAULT_KEK"), env::var("MPC_VAULT_PASSPHRASE")) {
+            (Ok(kek), _) => {
+                let bytes = hex::decode(kek.trim()).map_err(|e| format!("MPC_VAULT_KEK is not hex: {}", e))?;
+                let kek: [u8; 32] = bytes.try_into().map_err(|_| "MPC_VAULT_KEK must be 32 bytes".to_string())?;
+                KeyEncryptionKey::Raw(kek)
+            }
+            (Err(_), Ok(passphrase)) if !passphrase.is_empty() => KeyEncryptionKey::Passphrase(passphrase),
+            _ => return Err("Set MPC_VAULT_KEK or MPC_VAULT_PASSPHRASE to encrypt key shares".to_string()),
+        };
+
+        Ok(VaultConfig { dir, kek })
+    }
+}
+
+/// Peers of the network, listed in the registry file at `MPC_PEER_REGISTRY`
+///
+/// The identity key of the party is read from `MPC_KEY_FILE`. Both default to `peers.json` and
+/// `party_{i}_key.json` in `MPC_DATA_DIR`, itself `src/data` by default.
+///
+/// Peers with addresses in the registry are dialed directly, the others are discovered over mDNS
+/// unless `MPC_MDNS=false`. `MPC_TRANSPORTS` lists the enabled transports, `quic,tcp` by default, and
+/// `MPC_LISTEN_ADDRS` the comma separated multiaddrs to listen on instead of the registry ones.
+/// A protocol run is aborted after waiting `MPC_ROUND_TIMEOUT_SECS` for a message, 5 minutes by default,
+/// or after running `MPC_SESSION_TIMEOUT_SECS`, 30 minutes by default. At startup the node waits
+/// `MPC_STARTUP_TIMEOUT_SECS` for every party, 30 seconds by default, then serves with the ones connected.
+pub struct NetworkConfig {
+    pub registry_path: PathBuf,
+    pub options: NetworkOptions,
+}
+
+impl NetworkConfig {
+    pub fn from_env(local_party_id: u16) -> Result<NetworkConfig, String> {
+        let data_dir = env::var("MPC_DATA_DIR").unwrap_or_else(|_| DEFAULT_DATA_DIR.to_string());
+        let registry_path = env_path("MPC_PEER_REGISTRY").unwrap_or_else(|| Path::new(&data_dir).join("peers.json"));
+        let key_file = env_path("MPC_KEY_FILE").unwrap_or_else(|| Path::new(&data_dir).join(format!("party_{}_key.json", local_party_id)));
+        let mut options = NetworkOptions { mdns: env_bool("MPC_MDNS", true)?, key_file: Some(key_file), ..Default::default() };
+        if let Ok(transports) = env::var("MPC_TRANSPORTS") {
+            options.transports = env_list(&transports).map(|t| t.parse::<Transport>()).collect::<Result<_, _>>()
+                .map_err(|e| format!("MPC_TRANSPORTS: {}", e))?;
+        }
+        if let Ok(addrs) = env::var("MPC_LISTEN_ADDRS") {
+            options.listen_addrs = env_list(&addrs)
+                .map(|addr| addr.parse().map_err(|e| format!("MPC_LISTEN_ADDRS: invalid address {}: {}", addr, e)))
+                .collect::<Result<_, _>>()?;
+        }
+        let defaults = SessionTimeouts::default();
+        let round = env_u64("MPC_ROUND_TIMEOUT_SECS", defaults.round.as_secs())?;
+        let session = env_u64("MPC_SESSION_TIMEOUT_SECS", defaults.session.as_secs())?;
+        if round == 0 || session == 0 {
+            return Err("MPC_ROUND_TIMEOUT_SECS and MPC_SESSION_TIMEOUT_SECS must be at least 1".to_string());
+        }
+        options.timeouts = SessionTimeouts { round: Duration::from_secs(round), session: Duration::from_secs(session) };
+        options.startup_timeout = Duration::from_secs(env_u64("MPC_STARTUP_TIMEOUT_SECS", options.startup_timeout.as_secs())?);
+        Ok(NetworkConfig { registry_path, options })
+    }
+}
+
+const DEFAULT_CORS_ORIGINS: &str = "http://localhost:3000";
+
+/// Front-end origins allowed to call the API from a browser, comma separated in `MPC_CORS_ORIGINS`
+pub struct CorsConfig {
+    pub origins: Vec<HeaderValue>,
+}
+
+impl CorsConfig {
+    pub fn from_env() -> Result<CorsConfig, String> {
+        let origins = env::var("MPC_CORS_ORIGINS").unwrap_or_else(|_| DEFAULT_CORS_ORIGINS.to_string());
+        let origins = env_list(&origins)
+            .map(|origin| origin.parse().map_err(|e| format!("MPC_CORS_ORIGINS: invalid origin {}: {}", origin, e)))
+            .collect::<Result<_, _>>()?;
+        Ok(CorsConfig { origins })
+    }
+}
+
+const DEFAULT_PRESIGNATURE_TTL_SECS: u64 = 3600;
+
+/// How long a presignature stays usable, `MPC_PRESIGNATURE_TTL_SECS`, one hour by default
+pub struct PresignatureConfig {
+    pub ttl: Duration,
+}
+
+impl PresignatureConfig {
+    pub fn from_env() -> Result<PresignatureConfig, String> {
+        let ttl = env_u64("MPC_PRESIGNATURE_TTL_SECS", DEFAULT_PRESIGNATURE_TTL_SECS)?;
+        Ok(PresignatureConfig { ttl: Duration::from_secs(ttl) })
+    }
+}
+
+const DEFAULT_PRIME_POOL_SIZE: u64 = 2;
+const DEFAULT_PRIME_POOL_WORKERS: u64 = 1;
+
+/// Background Paillier prime generation
+///
+/// `MPC_PRIME_POOL_SIZE` sets how many sets of primes are kept ready, `MPC_PRIME_POOL_WORKERS` how many
+/// threads search for them. Ready primes are stored encrypted in the vault unless `MPC_PRIME_POOL_PERSIST=false`.
+pub struct PrimePoolConfig {
+    pub size: usize,
+    pub workers: usize,
+    pub persist: bool,
+}
+
+impl PrimePoolConfig {
+    pub fn from_env() -> Result<PrimePoolConfig, String> {
+        let size = env_u64("MPC_PRIME_POOL_SIZE", DEFAULT_PRIME_POOL_SIZE)? as usize;
+        let workers = env_u64("MPC_PRIME_POOL_WORKERS", DEFAULT_PRIME_POOL_WORKERS)? as usize;
+        if workers == 0 {
+            return Err("MPC_PRIME_POOL_WORKERS must be at least 1".to_string());
+        }
+        let persist = env_bool("MPC_PRIME_POOL_PERSIST", true)?;
+        Ok(PrimePoolConfig { size, workers, persist })
+    }
+}
+
+fn env_u64(name: &str, default: u64) -> Result<u64, String> {
+    match env::var(name) {
+        Ok(value) => value.trim().parse().map_err(|e| format!("{} is not a number: {}", name, e)),
+        Err(_) => Ok(default),
+    }
+}
+fn env_bool(name: &str, default: bool) -> Result<bool, String> {
+    match env::var(name) {
+        Ok(value) => value.trim().parse().map_err(|e| format!("{} is not a boolean: {}", name, e)),
+        Err(_) => Ok(default),
+    }
+}
+
+fn env_path(name: &str) -> Option<PathBuf> {
+    env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from)
+}
+
+fn env_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|item| !item.is_empty())
+}
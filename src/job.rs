@@ -0,0 +1,128 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use chrono::{DateTime, Duration, Utc};
+use cggmp21::progress::{Event, Tracer};
+use serde::Serialize;
+use uuid::Uuid;
+
+// Finished jobs are kept around this long so clients can still collect the result
+const FINISHED_JOB_RETENTION_HOURS: i64 = 24;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobKind {
+    Keygen,
+    AuxInfo,
+    Sign,
+    SignEthTransaction,
+    Presign,
+    Refresh,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    Running { protocol: &'static str, round: u16 },
+    Done,
+    Failed { reason: String },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Job {
+    pub id: Uuid,
+    pub kind: JobKind,
+    #[serde(flatten)]
+    pub status: JobStatus,
+    pub result: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Keeps track of every protocol run submitted through the job API
+#[derive(Default)]
+pub struct JobRegistry {
+    jobs: Mutex<HashMap<Uuid, Job>>,
+}
+
+impl JobRegistry {
+    pub fn create(&self, kind: JobKind) -> Uuid {
+        let now = Utc::now();
+        let job = Job {
+            id: Uuid::new_v4(),
+            kind,
+            status: JobStatus::Pending,
+            result: None,
+            created_at: now,
+            updated_at: now,
+        };
+        let id = job.id;
+
+        let mut jobs = self.jobs.lock().expect("Cannot lock jobs");
+        jobs.retain(|_, job| {
+            matches!(job.status, JobStatus::Pending | JobStatus::Running { .. })
+                || now - job.updated_at < Duration::hours(FINISHED_JOB_RETENTION_HOURS)
+        });
+        jobs.insert(id, job);
+        id
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<Job> {
+        self.jobs.lock().expect("Cannot lock jobs").get(id).cloned()
+    }
+
+    pub fn set_status(&self, id: &Uuid, status: JobStatus) {
+        if let Some(job) = self.jobs.lock().expect("Cannot lock jobs").get_mut(id) {
+            job.status = status;
+            job.updated_at = Utc::now();
+        }
+    }
+
+    pub fn finish(&self, id: &Uuid, result: Result<serde_json::Value, String>) {
+        if let Some(job) = self.jobs.lock().expect("Cannot lock jobs").get_mut(id) {
+            match result {
+                Ok(value) => {
+                    job.status = JobStatus::Done;
+                    job.result = Some(value);
+                }
+                Err(reason) => job.status = JobStatus::Failed { reason },
+            }
+            job.updated_at = Utc::now();
+        }
+    }
+}
+
+/// Reports the round a protocol has reached to the job it runs for
+pub struct JobTracer {
+    jobs: Arc<JobRegistry>,
+    id: Uuid,
+    protocol: &'static str,
+    round: u16,
+}
+
+impl JobTracer {
+    pub fn new(jobs: Arc<JobRegistry>, id: Uuid) -> JobTracer {
+        JobTracer { jobs, id, protocol: "", round: 0 }
+    }
+
+    /// Marks the start of the next protocol of the job, rounds are counted from scratch
+    pub fn begin(&mut self, protocol: &'static str) -> &mut JobTracer {
+        self.protocol = protocol;
+        self.round = 0;
+        self.report();
+        self
+    }
+
+    fn report(&self) {
+        self.jobs.set_status(&self.id, JobStatus::Running { protocol: self.protocol, round: self.round });
+    }
+}
+
+impl Tracer for JobTracer {
+    fn trace_event(&mut self, event: Event) {
+        if let Event::RoundBegins { .. } = event {
+            self.round += 1;
+            self.report();
+        }
+    }
+}
@@ -4,11 +4,27 @@ pub mod off_chain{
     pub mod utils; 
     pub mod common; 
     pub mod protocol; 
+    pub mod vault;
+    pub mod signing;
+    pub mod ethereum;
+    pub mod eip712;
+    pub mod presignature;
+    pub mod primes;
     pub mod network{
         pub mod sink; 
         pub mod stream;
         pub mod behaviour;
-        pub mod hash_map;
+        pub mod registry;
+        pub mod dialer;
+        pub mod direct;
+        pub mod driver;
+        pub mod channel;
+        pub mod echo;
+        pub mod deadline;
+        pub mod envelope;
+        pub mod error;
         pub mod setup;
+        pub mod node;
+        pub mod session;
     }
 }
\ No newline at end of file
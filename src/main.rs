@@ -1,26 +1,91 @@
+mod config;
 mod handler;
+mod job;
 mod model;
 mod response;
 mod route;
+mod scheduler;
+mod service;
+
+use std::{env, process::ExitCode, sync::Arc};
 
 use axum::http::{
     header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
-    HeaderValue, Method,
+    Method,
 };
-use route::create_router;
+use mpc_service::off_chain::{network::{node::Node, registry::PeerRegistry}, presignature::PresignaturePool, primes::PrimePool, vault::KeyVault};
+use config::{CorsConfig, NetworkConfig, PresignatureConfig, PrimePoolConfig, VaultConfig};
+use job::JobRegistry;
+use route::{create_router, AppState};
 use tower_http::cors::CorsLayer;
 
+const USAGE: &str = "usage: mpc_service <party index> <number of parties> [port]";
+
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run() -> Result<(), String> {
+    let args: Vec<String> = env::args().collect();
+    let (Some(local_party_id), Some(n)) = (args.get(1), args.get(2)) else {
+        return Err(USAGE.to_string());
+    };
+    let local_party_id: u16 = local_party_id.parse().map_err(|e| format!("Invalid party index {}: {}\n{}", local_party_id, e, USAGE))?;
+    let n: u16 = n.parse().map_err(|e| format!("Invalid number of parties {}: {}\n{}", n, e, USAGE))?;
+    let port: u16 = match args.get(3) {
+        Some(port) => port.parse().map_err(|e| format!("Invalid port {}: {}\n{}", port, e, USAGE))?,
+        None => 3000 + local_party_id,
+    };
+
+    let cors_config = CorsConfig::from_env().map_err(|e| format!("Invalid CORS configuration: {}", e))?;
     let cors = CorsLayer::new()
-        .allow_origin("http://localhost:3000".parse::<HeaderValue>().unwrap())
-        .allow_methods([Method::GET])
+        .allow_origin(cors_config.origins)
+        .allow_methods([Method::GET, Method::POST, Method::PUT])
         .allow_credentials(true)
         .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE]);
 
-    let app = create_router().layer(cors);
+    let vault_config = VaultConfig::from_env(local_party_id).map_err(|e| format!("Invalid vault configuration: {}", e))?;
+    let vault = Arc::new(KeyVault::open(&vault_config.dir, vault_config.kek).map_err(|e| format!("Cannot open key vault: {}", e))?);
+    let presignature_config = PresignatureConfig::from_env().map_err(|e| format!("Invalid presignature configuration: {}", e))?;
+    let prime_pool_config = PrimePoolConfig::from_env().map_err(|e| format!("Invalid prime pool configuration: {}", e))?;
+
+    // Started before the node, prime search runs while the peers connect
+    let primes = PrimePool::start(
+        prime_pool_config.size,
+        prime_pool_config.workers,
+        prime_pool_config.persist.then(|| Arc::clone(&vault)),
+    );
+
+    let network_config = NetworkConfig::from_env(local_party_id).map_err(|e| format!("Invalid network configuration: {}", e))?;
+    let registry = PeerRegistry::load(&network_config.registry_path).map_err(|e| format!("Cannot load peer registry: {}", e))?;
+    if registry.n() != n {
+        return Err(format!("The peer registry lists {} parties, not {}", registry.n(), n));
+    }
+
+    let node = Node::start(local_party_id, Arc::new(registry), &network_config.options).await.map_err(|e| format!("Cannot start party node: {}", e))?;
+    let unreachable = node.unreachable(&(0..n).collect::<Vec<_>>());
+    println!("Party {} connected to {} of {} peers", local_party_id, usize::from(n - 1) - unreachable.len(), n - 1);
+
+    let state = AppState {
+        node,
+        jobs: Arc::new(JobRegistry::default()),
+        vault,
+        presignatures: Arc::new(PresignaturePool::new(presignature_config.ttl)),
+        primes: Arc::new(primes),
+    };
+
+    tokio::spawn(scheduler::run_refresh_scheduler(state.clone()));
+
+    let app = create_router(state).layer(cors);
 
     println!("🚀 Server started successfully");
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port)).await.map_err(|e| format!("Cannot listen on port {}: {}", port, e))?;
+    axum::serve(listener, app).await.map_err(|e| format!("Server failed: {}", e))
 }
\ No newline at end of file
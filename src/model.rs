@@ -1,21 +1,72 @@
+use mpc_service::off_chain::{eip712::TypedData, ethereum::{Address, UnsignedTransaction}, signing::HashAlgorithm};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct KeyGenerationReqBody {
-    pub exec_id: &'static[u8],
-    pub n: Option<u16>,
-    pub local_party_id: Option<u16>,
+    pub exec_id: String,
     pub t: u16,
 }
 
 #[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct SignTransactionReqBody {
-    pub exec_id: &'static[u8],
-    pub n: Option<u16>,
-    pub local_party_id: Option<u16>,
-    pub incomplete_key_share: String,
+    pub exec_id: String,
+    pub key_id: String,
     pub entry: String,
     pub viewing_sk: String,
     pub view_tag_version: usize,
     pub viewtag: String,
+    /// Hex encoded 32-byte digest, signed as is
+    pub digest: Option<String>,
+    /// Hex encoded message, hashed with `hash` before signing
+    pub message: Option<String>,
+    pub hash: Option<HashAlgorithm>,
+    /// EIP-712 typed data, signed instead of a digest or a message
+    pub typed_data: Option<TypedData>,
+    /// Signs in a single round with this presignature from the pool
+    pub presignature_id: Option<String>,
+    /// Keygen indices of the parties signing, every party when missing
+    pub signers: Option<Vec<u16>>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub struct AuxInfoReqBody {
+    pub exec_id: String,
+    pub key_id: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SignEthTransactionReqBody {
+    pub exec_id: String,
+    pub key_id: String,
+    pub entry: String,
+    pub viewing_sk: String,
+    pub view_tag_version: usize,
+    pub viewtag: String,
+    pub transaction: UnsignedTransaction,
+    /// Rejects the request up front if the tweaked key does not control this address
+    pub stealth_address: Option<Address>,
+    pub presignature_id: Option<String>,
+    /// Keygen indices of the parties signing, every party when missing
+    pub signers: Option<Vec<u16>>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub struct PresignReqBody {
+    pub exec_id: String,
+    pub key_id: String,
+    pub count: u16,
+    /// Keygen indices of the parties that will sign with the presignatures, every party when missing
+    pub signers: Option<Vec<u16>>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub struct RefreshReqBody {
+    pub exec_id: String,
+    pub key_id: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub struct RefreshScheduleReqBody {
+    /// Seconds between two refreshes, no periodic refresh when missing
+    pub interval_secs: Option<u64>,
 }
\ No newline at end of file
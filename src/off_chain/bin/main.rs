@@ -1,6 +1,6 @@
 use tracing_subscriber::EnvFilter;
-use std::{env, error::Error};
-use mpc_service::off_chain::protocol::MpcCurvy;
+use std::{env, error::Error, path::{Path, PathBuf}, sync::Arc};
+use mpc_service::off_chain::{network::{registry::{PeerRegistry, DEFAULT_DATA_DIR}, setup::NetworkOptions}, protocol::MpcCurvy, signing::{HashAlgorithm, SigningInput}};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>>{
@@ -9,13 +9,25 @@ async fn main() -> Result<(), Box<dyn Error>>{
         .try_init();
 
     let args: Vec<String> = env::args().collect();
-    let local_party_id = u16::from_str_radix(&args[1], 10).unwrap();
+    let local_party_id = args[1].parse::<u16>().unwrap();
 
-    let n = 2;
+    // Same settings as the service, see its config module
+    let data_dir = env::var("MPC_DATA_DIR").unwrap_or_else(|_| DEFAULT_DATA_DIR.to_string());
+    let registry_path = env::var("MPC_PEER_REGISTRY").map(PathBuf::from).unwrap_or_else(|_| Path::new(&data_dir).join("peers.json"));
+    let key_file = env::var("MPC_KEY_FILE").map(PathBuf::from).unwrap_or_else(|_| Path::new(&data_dir).join(format!("party_{}_key.json", local_party_id)));
+    let registry = Arc::new(PeerRegistry::load(registry_path)?);
+    // Every party of the registry takes part, any two of them can sign
+    let t = 2;
 
-    let protocol = MpcCurvy::new(local_party_id, n).await?; 
+    // Signs the given hex encoded 32-byte digest, or the sha256 of "hello world" if none is given
+    let signing_input = match args.get(2){
+        Some(digest) => SigningInput::from_hex(Some(digest), None, None)?,
+        None => SigningInput::Message { message: b"hello world".to_vec(), hash: HashAlgorithm::Sha256 },
+    };
 
-    protocol.run().await?;
+    let protocol = MpcCurvy::new(local_party_id, registry, &NetworkOptions { key_file: Some(key_file), ..Default::default() }).await?.set_threshold(t);
+
+    protocol.run(signing_input).await?;
 
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,356 @@
+use std::{collections::{BTreeMap, BTreeSet}, error::Error, fmt};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha3::{Digest, Keccak256};
+
+const DOMAIN_TYPE: &str = "EIP712Domain";
+
+// Fields of the domain in the order EIP-712 lists them, used when the caller leaves the domain type out
+const DOMAIN_FIELDS: [(&str, &str); 5] = [
+    ("name", "string"),
+    ("version", "string"),
+    ("chainId", "uint256"),
+    ("verifyingContract", "address"),
+    ("salt", "bytes32"),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypedDataField{
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+}
+
+/// EIP-712 typed data, in the JSON format of `eth_signTypedData_v4`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedData{
+    pub types: BTreeMap<String, Vec<TypedDataField>>,
+    pub primary_type: String,
+    pub domain: Value,
+    pub message: Value,
+}
+
+#[derive(Debug)]
+pub struct TypedDataError(pub String);
+
+impl fmt::Display for TypedDataError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid typed data: {}", self.0)
+    }
+}
+
+impl Error for TypedDataError{}
+
+fn err<T>(msg: impl Into<String>) -> Result<T, TypedDataError>{
+    Err(TypedDataError(msg.into()))
+}
+
+impl TypedData{
+    /// `keccak256(0x19 0x01 || domainSeparator || hashStruct(message))`
+    pub fn signing_hash(&self) -> Result<[u8; 32], TypedDataError>{
+        let types = self.types_with_domain();
+
+        let mut payload = vec![0x19, 0x01];
+        payload.extend_from_slice(&hash_struct(&types, DOMAIN_TYPE, &self.domain)?);
+        // Signing the domain itself leaves the message hash out
+        if self.primary_type != DOMAIN_TYPE{
+            payload.extend_from_slice(&hash_struct(&types, &self.primary_type, &self.message)?);
+        }
+        Ok(Keccak256::digest(payload).into())
+    }
+
+    pub fn domain_separator(&self) -> Result<[u8; 32], TypedDataError>{
+        hash_struct(&self.types_with_domain(), DOMAIN_TYPE, &self.domain)
+    }
+
+    fn types_with_domain(&self) -> Types{
+        let mut types = self.types.clone();
+        if !types.contains_key(DOMAIN_TYPE){
+            let fields = DOMAIN_FIELDS.iter()
+                .filter(|(name, _)| self.domain.get(name).is_some())
+                .map(|(name, ty)| TypedDataField { name: name.to_string(), ty: ty.to_string() })
+                .collect();
+            types.insert(DOMAIN_TYPE.to_string(), fields);
+        }
+        types
+    }
+}
+
+type Types = BTreeMap<String, Vec<TypedDataField>>;
+
+fn hash_struct(types: &Types, ty: &str, value: &Value) -> Result<[u8; 32], TypedDataError>{
+    Ok(Keccak256::digest(encode_data(types, ty, value)?).into())
+}
+
+fn type_hash(types: &Types, ty: &str) -> Result<[u8; 32], TypedDataError>{
+    Ok(Keccak256::digest(encode_type(types, ty)?).into())
+}
+
+/// `Primary(type name,...)` followed by every referenced struct type, sorted by name
+fn encode_type(types: &Types, primary: &str) -> Result<String, TypedDataError>{
+    let mut deps = BTreeSet::new();
+    collect_dependencies(types, primary, &mut deps)?;
+    deps.remove(primary);
+
+    let mut encoded = String::new();
+    for ty in std::iter::once(primary).chain(deps.iter().map(String::as_str)){
+        let fields: Vec<String> = types[ty].iter().map(|field| format!("{} {}", field.ty, field.name)).collect();
+        encoded.push_str(&format!("{}({})", ty, fields.join(",")));
+    }
+    Ok(encoded)
+}
+
+fn collect_dependencies(types: &Types, ty: &str, deps: &mut BTreeSet<String>) -> Result<(), TypedDataError>{
+    let Some(fields) = types.get(ty) else {
+        return err(format!("unknown type {}", ty));
+    };
+    if !deps.insert(ty.to_string()){
+        return Ok(());
+    }
+    for field in fields{
+        let base = element_type(&field.ty);
+        if types.contains_key(base){
+            collect_dependencies(types, base, deps)?;
+        }
+    }
+    Ok(())
+}
+
+// Strips every array suffix, `Person[][2]` is made of `Person`
+fn element_type(ty: &str) -> &str{
+    ty.split('[').next().unwrap_or(ty)
+}
+
+fn encode_data(types: &Types, ty: &str, value: &Value) -> Result<Vec<u8>, TypedDataError>{
+    let Some(object) = value.as_object() else {
+        return err(format!("value of {} must be an object", ty));
+    };
+
+    let mut encoded = type_hash(types, ty)?.to_vec();
+    for field in &types[ty]{
+        let value = object.get(&field.name).unwrap_or(&Value::Null);
+        encoded.extend_from_slice(&encode_value(types, &field.ty, value)
+            .map_err(|e| TypedDataError(format!("{}.{}: {}", ty, field.name, e.0)))?);
+    }
+    Ok(encoded)
+}
+
+fn encode_value(types: &Types, ty: &str, value: &Value) -> Result<[u8; 32], TypedDataError>{
+    if let Some(inner) = ty.strip_suffix(']'){
+        let (base, len) = inner.rsplit_once('[').ok_or_else(|| TypedDataError(format!("malformed type {}", ty)))?;
+        let Some(items) = value.as_array() else {
+            return err("expected an array");
+        };
+        if !len.is_empty() && len.parse::<usize>().ok() != Some(items.len()){
+            return err(format!("expected {} items", len));
+        }
+        let mut encoded = Vec::with_capacity(items.len() * 32);
+        for item in items{
+            encoded.extend_from_slice(&encode_value(types, base, item)?);
+        }
+        return Ok(Keccak256::digest(encoded).into());
+    }
+
+    if types.contains_key(ty){
+        return hash_struct(types, ty, value);
+    }
+
+    match ty{
+        "string" => match value.as_str(){
+            Some(s) => Ok(Keccak256::digest(s.as_bytes()).into()),
+            None => err("expected a string"),
+        },
+        "bytes" => Ok(Keccak256::digest(decode_hex(value)?).into()),
+        "bool" => match value.as_bool(){
+            Some(b) => Ok(encode_word(&[b as u8])),
+            None => err("expected a boolean"),
+        },
+        "address" => {
+            let bytes = decode_hex(value)?;
+            if bytes.len() != 20{
+                return err("address must be 20 bytes");
+            }
+            Ok(encode_word(&bytes))
+        }
+        _ => {
+            if let Some(size) = ty.strip_prefix("bytes"){
+                let size = parse_size(size, 1, 32, ty)?;
+                let bytes = decode_hex(value)?;
+                if bytes.len() != size{
+                    return err(format!("expected {} bytes", size));
+                }
+                let mut word = [0u8; 32];
+                word[..size].copy_from_slice(&bytes);
+                Ok(word)
+            }else if let Some(bits) = ty.strip_prefix("uint"){
+                encode_integer(value, parse_size(bits, 8, 256, ty)?, false)
+            }else if let Some(bits) = ty.strip_prefix("int"){
+                encode_integer(value, parse_size(bits, 8, 256, ty)?, true)
+            }else{
+                err(format!("unknown type {}", ty))
+            }
+        }
+    }
+}
+
+fn parse_size(size: &str, min: usize, max: usize, ty: &str) -> Result<usize, TypedDataError>{
+    match size.parse::<usize>(){
+        Ok(size) if size >= min && size <= max && (min == 1 || size % 8 == 0) => Ok(size),
+        _ => err(format!("unknown type {}", ty)),
+    }
+}
+
+// Left pads to 32 bytes
+fn encode_word(bytes: &[u8]) -> [u8; 32]{
+    let mut word = [0u8; 32];
+    word[32 - bytes.len()..].copy_from_slice(bytes);
+    word
+}
+
+fn decode_hex(value: &Value) -> Result<Vec<u8>, TypedDataError>{
+    let Some(s) = value.as_str() else {
+        return err("expected a hex string");
+    };
+    hex::decode(s.strip_prefix("0x").unwrap_or(s)).map_err(|e| TypedDataError(e.to_string()))
+}
+
+/// Encodes a JSON number, or a decimal or `0x` hex string, as a 32-byte two's complement word
+fn encode_integer(value: &Value, bits: usize, signed: bool) -> Result<[u8; 32], TypedDataError>{
+    let text = match value{
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s.clone(),
+        _ => return err("expected an integer"),
+    };
+    let (negative, digits) = match text.strip_prefix('-'){
+        Some(digits) => (true, digits),
+        None => (false, text.as_str()),
+    };
+    if negative && !signed{
+        return err("expected an unsigned integer");
+    }
+
+    let mut word = match digits.strip_prefix("0x"){
+        Some(hex_digits) => parse_digits(hex_digits, 16)?,
+        None => parse_digits(digits, 10)?,
+    };
+    if negative && word != [0u8; 32]{
+        // -x = !x + 1
+        for byte in word.iter_mut(){
+            *byte = !*byte;
+        }
+        for byte in word.iter_mut().rev(){
+            let (sum, carry) = byte.overflowing_add(1);
+            *byte = sum;
+            if !carry{
+                break;
+            }
+        }
+        if word[0] & 0x80 == 0{
+            return err(format!("does not fit in int{}", bits));
+        }
+    }else if signed && word[0] & 0x80 != 0{
+        return err(format!("does not fit in int{}", bits));
+    }
+
+    // Every byte above the type width must be the sign extension of the value
+    let width = bits / 8;
+    let extension = if signed && word[32 - width] & 0x80 != 0 { 0xff } else { 0x00 };
+    if word[..32 - width].iter().any(|&byte| byte != extension){
+        return err(format!("does not fit in {}int{}", if signed { "" } else { "u" }, bits));
+    }
+    Ok(word)
+}
+
+fn parse_digits(digits: &str, radix: u32) -> Result<[u8; 32], TypedDataError>{
+    if digits.is_empty(){
+        return err("expected an integer");
+    }
+    let mut word = [0u8; 32];
+    for c in digits.chars(){
+        let Some(digit) = c.to_digit(radix) else {
+            return err(format!("invalid digit {}", c));
+        };
+        // word = word * radix + digit
+        let mut carry = digit;
+        for byte in word.iter_mut().rev(){
+            let x = *byte as u32 * radix + carry;
+            *byte = x as u8;
+            carry = x >> 8;
+        }
+        if carry != 0{
+            return err("integer overflows 256 bits");
+        }
+    }
+    Ok(word)
+}
+
+#[cfg(test)]
+mod eip712_tests {
+    use super::*;
+
+    // "Mail" example from the EIP-712 specification
+    fn mail() -> TypedData{
+        serde_json::from_value(serde_json::json!({
+            "types": {
+                "EIP712Domain": [
+                    { "name": "name", "type": "string" },
+                    { "name": "version", "type": "string" },
+                    { "name": "chainId", "type": "uint256" },
+                    { "name": "verifyingContract", "type": "address" }
+                ],
+                "Person": [
+                    { "name": "name", "type": "string" },
+                    { "name": "wallet", "type": "address" }
+                ],
+                "Mail": [
+                    { "name": "from", "type": "Person" },
+                    { "name": "to", "type": "Person" },
+                    { "name": "contents", "type": "string" }
+                ]
+            },
+            "primaryType": "Mail",
+            "domain": {
+                "name": "Ether Mail",
+                "version": "1",
+                "chainId": 1,
+                "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+            },
+            "message": {
+                "from": { "name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826" },
+                "to": { "name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB" },
+                "contents": "Hello, Bob!"
+            }
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_mail_example() {
+        let mail = mail();
+        assert_eq!(encode_type(&mail.types, "Mail").unwrap(), "Mail(Person from,Person to,string contents)Person(string name,address wallet)");
+        assert_eq!(hex::encode(mail.domain_separator().unwrap()), "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f");
+        assert_eq!(hex::encode(mail.signing_hash().unwrap()), "be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2");
+
+        // The domain type is inferred from the domain fields when left out
+        let mut without_domain_type = mail.clone();
+        without_domain_type.types.remove(DOMAIN_TYPE);
+        assert_eq!(without_domain_type.signing_hash().unwrap(), mail.signing_hash().unwrap());
+    }
+
+    #[test]
+    fn test_integer_encoding() {
+        let json = |x: &str| Value::String(x.to_string());
+
+        assert_eq!(encode_integer(&json("-1"), 8, true).unwrap(), [0xff; 32]);
+        assert_eq!(encode_integer(&json("0xff"), 8, false).unwrap(), encode_word(&[0xff]));
+        assert_eq!(encode_integer(&serde_json::json!(1000), 16, false).unwrap(), encode_word(&[0x03, 0xe8]));
+        assert_eq!(encode_integer(&json("-128"), 8, true).unwrap()[31], 0x80);
+
+        assert!(encode_integer(&json("256"), 8, false).is_err());
+        assert!(encode_integer(&json("128"), 8, true).is_err());
+        assert!(encode_integer(&json("-129"), 8, true).is_err());
+        assert!(encode_integer(&json("-1"), 256, false).is_err());
+        assert!(encode_integer(&json(&"9".repeat(78)), 256, false).is_err());
+    }
+}
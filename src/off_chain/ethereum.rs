@@ -0,0 +1,455 @@
+use std::{error::Error, fmt};
+
+use cggmp21::{generic_ec::{Point, Scalar}, signing::Signature, supported_curves::Secp256k1};
+use rlp::RlpStream;
+use serde::{de, Deserialize, Deserializer};
+use sha3::{Digest, Keccak256};
+
+const EIP1559_TX_TYPE: u8 = 0x02;
+
+/// 20-byte Ethereum account address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Address(pub [u8; 20]);
+
+impl Address{
+    /// Address controlled by the given public key: last 20 bytes of the Keccak-256 of the uncompressed point
+    pub fn from_public_key(public_key: &Point<Secp256k1>) -> Address{
+        let uncompressed = public_key.to_bytes(false);
+        let hash = Keccak256::digest(&uncompressed[1..]);
+        let mut address = [0u8; 20];
+        address.copy_from_slice(&hash[12..]);
+        Address(address)
+    }
+}
+
+impl fmt::Display for Address{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+impl std::str::FromStr for Address{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s.strip_prefix("0x").unwrap_or(s)).map_err(|e| e.to_string())?;
+        let address = bytes.try_into().map_err(|_| "address must be 20 bytes".to_string())?;
+        Ok(Address(address))
+    }
+}
+
+impl<'de> Deserialize<'de> for Address{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+/// EIP-191 hash of a `personal_sign` message: `keccak256("\x19Ethereum Signed Message:\n" || len || message)`
+pub fn hash_personal_message(message: &[u8]) -> [u8; 32]{
+    let mut hasher = Keccak256::new();
+    hasher.update(format!("\x19Ethereum Signed Message:\n{}", message.len()));
+    hasher.update(message);
+    hasher.finalize().into()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccessListItem{
+    pub address: Address,
+    #[serde(default, deserialize_with = "deserialize_storage_keys")]
+    pub storage_keys: Vec<[u8; 32]>,
+}
+
+/// Unsigned EIP-1559 (type 2) transaction
+#[derive(Debug, Clone, Deserialize)]
+pub struct Eip1559Transaction{
+    #[serde(deserialize_with = "deserialize_quantity")]
+    pub chain_id: u64,
+    #[serde(deserialize_with = "deserialize_quantity")]
+    pub nonce: u64,
+    #[serde(deserialize_with = "deserialize_quantity")]
+    pub max_priority_fee_per_gas: u128,
+    #[serde(deserialize_with = "deserialize_quantity")]
+    pub max_fee_per_gas: u128,
+    #[serde(deserialize_with = "deserialize_quantity")]
+    pub gas_limit: u64,
+    /// `None` for contract creation
+    pub to: Option<Address>,
+    #[serde(default, deserialize_with = "deserialize_quantity")]
+    pub value: u128,
+    #[serde(default, deserialize_with = "deserialize_hex_bytes")]
+    pub data: Vec<u8>,
+    #[serde(default)]
+    pub access_list: Vec<AccessListItem>,
+}
+
+/// Unsigned legacy transaction, replay protected with EIP-155
+#[derive(Debug, Clone, Deserialize)]
+pub struct LegacyTransaction{
+    #[serde(deserialize_with = "deserialize_quantity")]
+    pub chain_id: u64,
+    #[serde(deserialize_with = "deserialize_quantity")]
+    pub nonce: u64,
+    #[serde(deserialize_with = "deserialize_quantity")]
+    pub gas_price: u128,
+    #[serde(deserialize_with = "deserialize_quantity")]
+    pub gas_limit: u64,
+    /// `None` for contract creation
+    pub to: Option<Address>,
+    #[serde(default, deserialize_with = "deserialize_quantity")]
+    pub value: u128,
+    #[serde(default, deserialize_with = "deserialize_hex_bytes")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum UnsignedTransaction{
+    Eip1559(Eip1559Transaction),
+    Legacy(LegacyTransaction),
+}
+
+impl UnsignedTransaction{
+    /// Keccak-256 of the transaction payload, the digest the sender signs
+    pub fn signing_hash(&self) -> [u8; 32]{
+        let payload = match self{
+            UnsignedTransaction::Eip1559(tx) => {
+                let mut stream = RlpStream::new_list(9);
+                tx.append_fields(&mut stream);
+                typed_payload(EIP1559_TX_TYPE, &stream.out())
+            }
+            UnsignedTransaction::Legacy(tx) => {
+                let mut stream = RlpStream::new_list(9);
+                tx.append_fields(&mut stream);
+                stream.append(&tx.chain_id).append(&0u8).append(&0u8);
+                stream.out().to_vec()
+            }
+        };
+        Keccak256::digest(payload).into()
+    }
+
+    /// Raw signed transaction, ready for `eth_sendRawTransaction`
+    pub fn encode_signed(&self, signature: &RecoverableSignature) -> Vec<u8>{
+        match self{
+            UnsignedTransaction::Eip1559(tx) => {
+                let mut stream = RlpStream::new_list(12);
+                tx.append_fields(&mut stream);
+                stream.append(&signature.y_parity);
+                append_signature(&mut stream, signature);
+                typed_payload(EIP1559_TX_TYPE, &stream.out())
+            }
+            UnsignedTransaction::Legacy(tx) => {
+                let mut stream = RlpStream::new_list(9);
+                tx.append_fields(&mut stream);
+                // EIP-155: v = chain_id * 2 + 35 + y_parity
+                let v = tx.chain_id as u128 * 2 + 35 + signature.y_parity as u128;
+                stream.append(&v);
+                append_signature(&mut stream, signature);
+                stream.out().to_vec()
+            }
+        }
+    }
+}
+
+impl Eip1559Transaction{
+    fn append_fields(&self, stream: &mut RlpStream){
+        stream
+            .append(&self.chain_id)
+            .append(&self.nonce)
+            .append(&self.max_priority_fee_per_gas)
+            .append(&self.max_fee_per_gas)
+            .append(&self.gas_limit);
+        append_to(stream, &self.to);
+        stream.append(&self.value).append(&self.data);
+
+        stream.begin_list(self.access_list.len());
+        for item in &self.access_list{
+            stream.begin_list(2).append(&item.address.0.as_slice());
+            stream.begin_list(item.storage_keys.len());
+            for key in &item.storage_keys{
+                stream.append(&key.as_slice());
+            }
+        }
+    }
+}
+
+impl LegacyTransaction{
+    fn append_fields(&self, stream: &mut RlpStream){
+        stream
+            .append(&self.nonce)
+            .append(&self.gas_price)
+            .append(&self.gas_limit);
+        append_to(stream, &self.to);
+        stream.append(&self.value).append(&self.data);
+    }
+}
+
+fn append_to(stream: &mut RlpStream, to: &Option<Address>){
+    match to{
+        Some(address) => stream.append(&address.0.as_slice()),
+        None => stream.append_empty_data(),
+    };
+}
+
+// r and s are encoded as integers, without leading zeros
+fn append_signature(stream: &mut RlpStream, signature: &RecoverableSignature){
+    stream.append(&trim_leading_zeros(&signature.r)).append(&trim_leading_zeros(&signature.s));
+}
+
+fn trim_leading_zeros(bytes: &[u8]) -> &[u8]{
+    let start = bytes.iter().position(|&b| b != 0).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+fn typed_payload(tx_type: u8, rlp: &[u8]) -> Vec<u8>{
+    let mut payload = Vec::with_capacity(rlp.len() + 1);
+    payload.push(tx_type);
+    payload.extend_from_slice(rlp);
+    payload
+}
+
+#[derive(Debug)]
+pub struct SignatureRecoveryError;
+
+impl fmt::Display for SignatureRecoveryError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "signature does not verify against the public key")
+    }
+}
+
+impl Error for SignatureRecoveryError{}
+
+/// Low-S ECDSA signature with the parity of R, from which the signer public key can be recovered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecoverableSignature{
+    pub r: [u8; 32],
+    pub s: [u8; 32],
+    pub y_parity: bool,
+}
+
+impl RecoverableSignature{
+    /// Normalizes the signature to low-S and computes its recovery id against `public_key`
+    pub fn from_signature(signature: Signature<Secp256k1>, public_key: &Point<Secp256k1>, digest: &[u8; 32]) -> Result<RecoverableSignature, SignatureRecoveryError>{
+        let signature = signature.normalize_s();
+        let z = Scalar::<Secp256k1>::from_be_bytes_mod_order(digest);
+        let r: Scalar<Secp256k1> = *signature.r;
+        let s_inv: Scalar<Secp256k1> = *signature.s.invert();
+
+        // R = (z * G + r * P) / s, its x coordinate must be r
+        let big_r = Point::generator() * (z * s_inv) + public_key * (r * s_inv);
+        let big_r = big_r.to_bytes(true);
+        if big_r.len() != 33 || big_r[1..] != *r.to_be_bytes(){
+            return Err(SignatureRecoveryError);
+        }
+
+        let mut out = RecoverableSignature { r: [0u8; 32], s: [0u8; 32], y_parity: big_r[0] == 0x03 };
+        out.r.copy_from_slice(&r.to_be_bytes());
+        out.s.copy_from_slice(&signature.s.to_be_bytes());
+        Ok(out)
+    }
+
+    /// `v` of a 65-byte signature: 27 + y_parity
+    pub fn v(&self) -> u8{
+        27 + self.y_parity as u8
+    }
+
+    /// 65-byte `r || s || v` encoding used by `personal_sign` and `ecrecover` callers
+    pub fn to_compact(&self) -> [u8; 65]{
+        let mut compact = [0u8; 65];
+        compact[..32].copy_from_slice(&self.r);
+        compact[32..64].copy_from_slice(&self.s);
+        compact[64] = self.v();
+        compact
+    }
+
+    /// ASN.1 DER encoding: `SEQUENCE { INTEGER r, INTEGER s }`
+    pub fn to_der(&self) -> Vec<u8>{
+        let r = der_integer(&self.r);
+        let s = der_integer(&self.s);
+        let mut der = vec![0x30, (r.len() + s.len()) as u8];
+        der.extend_from_slice(&r);
+        der.extend_from_slice(&s);
+        der
+    }
+}
+
+// Minimal big-endian integer, with a leading zero when the high bit is set so it stays positive
+fn der_integer(x: &[u8; 32]) -> Vec<u8>{
+    let x = trim_leading_zeros(x);
+    let pad = x.first().is_none_or(|&b| b & 0x80 != 0);
+    let mut encoded = vec![0x02, (x.len() + pad as usize) as u8];
+    if pad{
+        encoded.push(0);
+    }
+    encoded.extend_from_slice(x);
+    encoded
+}
+
+/// Accepts a JSON number, a decimal string or a `0x` prefixed hex string
+fn deserialize_quantity<'de, D: Deserializer<'de>, T: TryFrom<u128>>(deserializer: D) -> Result<T, D::Error>{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Quantity{
+        Number(u64),
+        Text(String),
+    }
+
+    let quantity = match Quantity::deserialize(deserializer)?{
+        Quantity::Number(x) => x as u128,
+        Quantity::Text(x) => match x.strip_prefix("0x"){
+            Some(hex) => u128::from_str_radix(hex, 16),
+            None => x.parse(),
+        }.map_err(de::Error::custom)?,
+    };
+    T::try_from(quantity).map_err(|_| de::Error::custom(format!("quantity {} is out of range", quantity)))
+}
+
+fn deserialize_hex_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error>{
+    let x = String::deserialize(deserializer)?;
+    hex::decode(x.strip_prefix("0x").unwrap_or(&x)).map_err(de::Error::custom)
+}
+
+fn deserialize_storage_keys<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<[u8; 32]>, D::Error>{
+    Vec::<String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|key| {
+            let bytes = hex::decode(key.strip_prefix("0x").unwrap_or(&key)).map_err(de::Error::custom)?;
+            bytes.try_into().map_err(|_| de::Error::custom("storage key must be 32 bytes"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod ethereum_tests {
+    use cggmp21::generic_ec::SecretScalar;
+
+    use super::*;
+
+    // Example transaction from EIP-155
+    fn eip155_example() -> UnsignedTransaction{
+        serde_json::from_value(serde_json::json!({
+            "type": "legacy",
+            "chain_id": 1,
+            "nonce": 9,
+            "gas_price": "20000000000",
+            "gas_limit": 21000,
+            "to": "0x3535353535353535353535353535353535353535",
+            "value": "0xde0b6b3a7640000",
+            "data": "0x"
+        })).unwrap()
+    }
+
+    // Signature of the EIP-155 example with private key 0x4646..46
+    fn eip155_example_signature() -> Signature<Secp256k1>{
+        let mut signature = [0u8; 64];
+        hex::decode_to_slice("28ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa63627667cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83", &mut signature).unwrap();
+        Signature::read_from_slice(&signature).unwrap()
+    }
+
+    #[test]
+    fn test_eip155_signed_transaction() {
+        let tx = eip155_example();
+        let digest = tx.signing_hash();
+        assert_eq!(hex::encode(digest), "daf5a779ae972f972197303d7b574746c7ef83eadac0f2791ad23db92e4c8e53");
+
+        let sk = SecretScalar::<Secp256k1>::from_be_bytes(&[0x46u8; 32]).unwrap();
+        let public_key = Point::generator() * &sk;
+        assert_eq!(Address::from_public_key(&public_key).to_string(), "0x9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f");
+
+        let signature = RecoverableSignature::from_signature(eip155_example_signature(), &public_key, &digest).unwrap();
+        assert!(!signature.y_parity);
+        assert_eq!(
+            hex::encode(tx.encode_signed(&signature)),
+            "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"
+        );
+
+        let other_key = Point::generator() * Scalar::<Secp256k1>::from_be_bytes_mod_order([7u8; 32]);
+        assert!(RecoverableSignature::from_signature(eip155_example_signature(), &other_key, &digest).is_err());
+    }
+
+    #[test]
+    fn test_quantities_as_hex_strings() {
+        let tx: UnsignedTransaction = serde_json::from_value(serde_json::json!({
+            "type": "legacy",
+            "chain_id": "0x1",
+            "nonce": "0x9",
+            "gas_price": "0x4a817c800",
+            "gas_limit": "0x5208",
+            "to": "0x3535353535353535353535353535353535353535",
+            "value": "1000000000000000000",
+            "data": "0x"
+        })).unwrap();
+        assert_eq!(tx.signing_hash(), eip155_example().signing_hash());
+
+        let tx: UnsignedTransaction = serde_json::from_value(serde_json::json!({
+            "type": "eip1559",
+            "chain_id": "0x1",
+            "nonce": "0x0",
+            "max_priority_fee_per_gas": "0x1",
+            "max_fee_per_gas": "0x2",
+            "gas_limit": "21000",
+            "to": null
+        })).unwrap();
+        let UnsignedTransaction::Eip1559(tx) = tx else { panic!("not an EIP-1559 transaction") };
+        assert_eq!((tx.chain_id, tx.nonce, tx.gas_limit), (1, 0, 21000));
+
+        let too_large = serde_json::from_value::<UnsignedTransaction>(serde_json::json!({
+            "type": "legacy",
+            "chain_id": 1,
+            "nonce": "0x10000000000000000",
+            "gas_price": 1,
+            "gas_limit": 21000,
+            "to": null
+        }));
+        assert!(too_large.is_err());
+    }
+
+    #[test]
+    fn test_signature_encodings() {
+        let mut signature = RecoverableSignature { r: [0u8; 32], s: [0u8; 32], y_parity: true };
+        signature.r[0] = 0x80;
+        signature.s[31] = 0x05;
+
+        let compact = signature.to_compact();
+        assert_eq!(&compact[..32], &signature.r);
+        assert_eq!(&compact[32..64], &signature.s);
+        assert_eq!(compact[64], 28);
+
+        let mut der = vec![0x30, 0x26, 0x02, 0x21, 0x00];
+        der.extend_from_slice(&signature.r);
+        der.extend_from_slice(&[0x02, 0x01, 0x05]);
+        assert_eq!(signature.to_der(), der);
+    }
+
+    #[test]
+    fn test_personal_message_hash() {
+        assert_eq!(hex::encode(hash_personal_message(b"hello world")), "d9eba16ed0ecae432b71fe008c98cc872bb4cc214d3220a36f365326cf807d68");
+    }
+
+    #[test]
+    fn test_eip1559_encoding() {
+        let tx: UnsignedTransaction = serde_json::from_value(serde_json::json!({
+            "type": "eip1559",
+            "chain_id": 1,
+            "nonce": 0,
+            "max_priority_fee_per_gas": 1,
+            "max_fee_per_gas": "0x2",
+            "gas_limit": 21000,
+            "to": null,
+            "data": "0x6000",
+            "access_list": [{ "address": "0x3535353535353535353535353535353535353535", "storage_keys": [] }]
+        })).unwrap();
+
+        let signature = RecoverableSignature { r: [1u8; 32], s: [2u8; 32], y_parity: true };
+        let raw = tx.encode_signed(&signature);
+        assert_eq!(raw[0], EIP1559_TX_TYPE);
+        assert_eq!(
+            hex::encode(&raw[1..]),
+            concat!(
+                "f8670180010282520880808260",
+                "00d7d6943535353535353535353535353535353535353535c0",
+                "01a00101010101010101010101010101010101010101010101010101010101010101",
+                "a00202020202020202020202020202020202020202020202020202020202020202"
+            )
+        );
+    }
+}
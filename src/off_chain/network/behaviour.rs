@@ -1,7 +1,11 @@
-use libp2p::{gossipsub, mdns, swarm::NetworkBehaviour};
+use libp2p::{gossipsub, mdns, request_response, swarm::{behaviour::toggle::Toggle, NetworkBehaviour}};
+
+use super::direct::DirectCodec;
 
 #[derive(NetworkBehaviour)]
 pub struct MyBehaviour {
     pub gossipsub: gossipsub::Behaviour,
-    pub mdns: mdns::tokio::Behaviour,
+    pub mdns: Toggle<mdns::tokio::Behaviour>,
+    /// Point-to-point protocol messages, see [`super::direct`]
+    pub direct: request_response::Behaviour<DirectCodec>,
 }
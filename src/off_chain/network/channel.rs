@@ -0,0 +1,178 @@
+use std::{error::Error, fmt};
+
+use chacha20poly1305::{aead::{Aead, Payload}, ChaCha20Poly1305, Key, KeyInit, Nonce};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use libp2p::identity::Keypair;
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+use super::{registry::PeerRegistry, session::SessionId};
+
+#[derive(Debug)]
+pub enum ChannelError{
+    /// Only ed25519 identities can be converted to X25519 keys
+    UnsupportedKey(u16),
+    UnknownParty(u16),
+    Malformed{ sender: u16 },
+    Decryption{ sender: u16, round: u16 },
+    RoundMismatch{ sender: u16, sealed: u16, actual: u16 },
+}
+
+impl fmt::Display for ChannelError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self{
+            ChannelError::UnsupportedKey(party) => write!(f, "party {} does not have an ed25519 identity key", party),
+            ChannelError::UnknownParty(party) => write!(f, "party {} is not in the peer registry", party),
+            ChannelError::Malformed { sender } => write!(f, "malformed encrypted message from party {}", sender),
+            ChannelError::Decryption { sender, round } => write!(f, "cannot decrypt message of round {} from party {}", round, sender),
+            ChannelError::RoundMismatch { sender, sealed, actual } => write!(f, "party {} sealed a round {} message for round {}", sender, sealed, actual),
+        }
+    }
+}
+
+impl Error for ChannelError{}
+
+/// P2P message encrypted to its recipient
+#[derive(Serialize, Deserialize)]
+struct SealedMessage{
+    round: u16,
+    ephemeral: [u8; 32],
+    ciphertext: Vec<u8>,
+}
+
+/// X25519 keys of the parties, converted from their ed25519 identities, to encrypt P2P messages end to end
+///
+/// Each message gets a fresh key from an ephemeral DH with the recipient and a static DH between sender and
+/// recipient, so only the recipient can read it and only the sender can have written it. The session id,
+/// round, sender and recipient are authenticated data, a message cannot be replayed into another context.
+pub struct ChannelKeys{
+    local_party_id: u16,
+    secret: StaticSecret,
+    publics: Vec<PublicKey>,
+}
+
+impl ChannelKeys{
+    pub fn new(keypair: &Keypair, registry: &PeerRegistry, local_party_id: u16) -> Result<ChannelKeys, ChannelError>{
+        let keypair = keypair.clone().try_into_ed25519().map_err(|_| ChannelError::UnsupportedKey(local_party_id))?;
+        // Same derivation as the ed25519 signing scalar, clamped by X25519
+        let hash = Sha512::digest(keypair.secret().as_ref());
+        let mut scalar = [0u8; 32];
+        scalar.copy_from_slice(&hash[..32]);
+
+        let publics = registry.peers()
+            .map(|peer| {
+                let public_key = peer.identity_key()
+                    .and_then(|public_key| public_key.try_into_ed25519().ok())
+                    .ok_or(ChannelError::UnsupportedKey(peer.party_index))?;
+                let montgomery = CompressedEdwardsY(public_key.to_bytes()).decompress()
+                    .ok_or(ChannelError::UnsupportedKey(peer.party_index))?
+                    .to_montgomery();
+                Ok(PublicKey::from(montgomery.to_bytes()))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(ChannelKeys { local_party_id, secret: StaticSecret::from(scalar), publics })
+    }
+
+    fn public(&self, party: u16) -> Result<&PublicKey, ChannelError>{
+        self.publics.get(usize::from(party)).ok_or(ChannelError::UnknownParty(party))
+    }
+
+    fn message_key(&self, ephemeral_dh: &[u8; 32], static_dh: &[u8; 32], ephemeral: &[u8; 32], sender: u16, recipient: u16) -> Result<Key, ChannelError>{
+        let mut hasher = Sha256::new();
+        hasher.update(b"cggmp21/p2p");
+        hasher.update(ephemeral_dh);
+        hasher.update(static_dh);
+        hasher.update(ephemeral);
+        hasher.update(self.public(sender)?.as_bytes());
+        hasher.update(self.public(recipient)?.as_bytes());
+        Ok(hasher.finalize())
+    }
+
+    fn associated_data(session_id: &SessionId, round: u16, sender: u16, recipient: u16) -> Vec<u8>{
+        let mut aad = bincode::serialize(session_id).expect("Cannot serialize session id");
+        aad.extend_from_slice(&round.to_be_bytes());
+        aad.extend_from_slice(&sender.to_be_bytes());
+        aad.extend_from_slice(&recipient.to_be_bytes());
+        aad
+    }
+
+    pub fn seal(&self, session_id: &SessionId, round: u16, recipient: u16, plaintext: &[u8]) -> Result<Vec<u8>, ChannelError>{
+        let recipient_public = self.public(recipient)?;
+        let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+        let ephemeral = PublicKey::from(&ephemeral_secret).to_bytes();
+        let ephemeral_dh = ephemeral_secret.diffie_hellman(recipient_public).to_bytes();
+        let static_dh = self.secret.diffie_hellman(recipient_public).to_bytes();
+
+        let key = self.message_key(&ephemeral_dh, &static_dh, &ephemeral, self.local_party_id, recipient)?;
+        let aad = Self::associated_data(session_id, round, self.local_party_id, recipient);
+        // Every message has its own key, a zero nonce is never reused
+        let ciphertext = ChaCha20Poly1305::new(&key)
+            .encrypt(&Nonce::default(), Payload { msg: plaintext, aad: &aad })
+            .map_err(|_| ChannelError::Malformed { sender: self.local_party_id })?;
+
+        Ok(bincode::serialize(&SealedMessage { round, ephemeral, ciphertext }).expect("Cannot serialize sealed msg"))
+    }
+
+    /// Decrypts a message of `sender` to the local party, returns the round it was sealed for
+    pub fn open(&self, session_id: &SessionId, sender: u16, sealed: &[u8]) -> Result<(u16, Vec<u8>), ChannelError>{
+        let sealed: SealedMessage = bincode::deserialize(sealed).map_err(|_| ChannelError::Malformed { sender })?;
+        let sender_public = self.public(sender)?;
+        let ephemeral_dh = self.secret.diffie_hellman(&PublicKey::from(sealed.ephemeral)).to_bytes();
+        let static_dh = self.secret.diffie_hellman(sender_public).to_bytes();
+
+        let key = self.message_key(&ephemeral_dh, &static_dh, &sealed.ephemeral, sender, self.local_party_id)?;
+        let aad = Self::associated_data(session_id, sealed.round, sender, self.local_party_id);
+        let plaintext = ChaCha20Poly1305::new(&key)
+            .decrypt(&Nonce::default(), Payload { msg: &sealed.ciphertext, aad: &aad })
+            .map_err(|_| ChannelError::Decryption { sender, round: sealed.round })?;
+
+        Ok((sealed.round, plaintext))
+    }
+}
+
+#[cfg(test)]
+mod channel_tests {
+    use super::*;
+    use crate::off_chain::network::session::ProtocolKind;
+
+    fn parties() -> Vec<ChannelKeys>{
+        let keypairs: Vec<Keypair> = (0..3).map(|_| Keypair::generate_ed25519()).collect();
+        // Party 1 has no public key listed, it is taken from its peer id
+        let peers: Vec<_> = keypairs.iter().enumerate().map(|(i, keypair)| serde_json::json!({
+            "party_index": i,
+            "peer_id": keypair.public().to_peer_id().to_string(),
+            "public_key": (i != 1).then(|| hex::encode(keypair.public().encode_protobuf())),
+        })).collect();
+        let registry = PeerRegistry::from_json(&serde_json::json!({ "peers": peers }).to_string()).unwrap();
+        keypairs.iter().enumerate().map(|(i, keypair)| ChannelKeys::new(keypair, &registry, i as u16).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_only_the_recipient_opens_a_message() {
+        let parties = parties();
+        let session_id = SessionId::new(ProtocolKind::Signing, b"exec");
+
+        let sealed = parties[0].seal(&session_id, 3, 1, b"share").unwrap();
+        assert_eq!(parties[1].open(&session_id, 0, &sealed).unwrap(), (3, b"share".to_vec()));
+
+        assert!(matches!(parties[2].open(&session_id, 0, &sealed), Err(ChannelError::Decryption { sender: 0, round: 3 })));
+        // Another party cannot claim to be the sender
+        assert!(parties[1].open(&session_id, 2, &sealed).is_err());
+        let other_session = SessionId::new(ProtocolKind::Presigning, b"exec");
+        assert!(parties[1].open(&other_session, 0, &sealed).is_err());
+    }
+
+    #[test]
+    fn test_sealed_round_is_authenticated() {
+        let parties = parties();
+        let session_id = SessionId::new(ProtocolKind::Keygen, b"exec");
+
+        let mut sealed: SealedMessage = bincode::deserialize(&parties[2].seal(&session_id, 1, 0, b"share").unwrap()).unwrap();
+        sealed.round = 2;
+        let tampered = bincode::serialize(&sealed).unwrap();
+        assert!(matches!(parties[0].open(&session_id, 2, &tampered), Err(ChannelError::Decryption { sender: 2, round: 2 })));
+    }
+}
@@ -0,0 +1,140 @@
+use std::{collections::BTreeMap, fmt, future::Future, pin::Pin, task::{Context, Poll}, time::Duration};
+
+use tokio::time::{sleep_until, Instant, Sleep};
+
+use super::{error::NetworkError, session::Participants};
+
+const DEFAULT_ROUND_TIMEOUT_SECS: u64 = 300;
+const DEFAULT_SESSION_TIMEOUT_SECS: u64 = 1800;
+
+/// How long a protocol run waits for the other parties before it is aborted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SessionTimeouts{
+    /// Longest wait for the next message of the protocol
+    pub round: Duration,
+    /// Longest run of the whole session
+    pub session: Duration,
+}
+
+impl Default for SessionTimeouts{
+    fn default() -> Self {
+        SessionTimeouts { round: Duration::from_secs(DEFAULT_ROUND_TIMEOUT_SECS), session: Duration::from_secs(DEFAULT_SESSION_TIMEOUT_SECS) }
+    }
+}
+
+/// Which of the [`SessionTimeouts`] expired
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timeout{
+    Round,
+    Session,
+}
+
+impl fmt::Display for Timeout{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self{
+            Timeout::Round => write!(f, "round"),
+            Timeout::Session => write!(f, "session"),
+        }
+    }
+}
+
+/// Timeouts of the incoming side of a session
+///
+/// The round timeout runs while the protocol waits for a message, from the first time the stream has
+/// nothing to hand it, and starts over with every message delivered. The time the protocol spends
+/// computing between two messages does not count. The session timeout runs from the creation of the stream.
+///
+/// The last round heard from each other participant is kept to tell which parties the run waits for:
+/// the ones that went silent first. Parties are keygen indexes.
+pub struct Deadline{
+    timeouts: SessionTimeouts,
+    session_end: Instant,
+    waiting_since: Option<Instant>,
+    timer: Pin<Box<Sleep>>,
+    last_heard: BTreeMap<u16, Option<u16>>,
+}
+
+impl Deadline{
+    pub fn new(timeouts: SessionTimeouts, local_party_id: u16, participants: &Participants) -> Deadline{
+        let session_end = Instant::now() + timeouts.session;
+        let last_heard = participants.parties().iter()
+            .filter(|party| **party != local_party_id)
+            .map(|party| (*party, None))
+            .collect();
+        Deadline { timeouts, session_end, waiting_since: None, timer: Box::pin(sleep_until(session_end)), last_heard }
+    }
+
+    /// Records an envelope of `sender` for `round`
+    pub fn heard(&mut self, sender: u16, round: u16){
+        if let Some(last) = self.last_heard.get_mut(&sender){
+            *last = (*last).max(Some(round));
+        }
+    }
+
+    /// The protocol got a message, the round timeout starts over on the next wait
+    pub fn delivered(&mut self){
+        self.waiting_since = None;
+    }
+
+    /// Ready with the timeout error once a deadline passed, to be polled while the stream has nothing to deliver
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<NetworkError>{
+        let waiting_since = *self.waiting_since.get_or_insert_with(Instant::now);
+        let round_end = waiting_since + self.timeouts.round;
+        let (timeout, end) = if round_end < self.session_end { (Timeout::Round, round_end) } else { (Timeout::Session, self.session_end) };
+
+        if self.timer.deadline() != end{
+            self.timer.as_mut().reset(end);
+        }
+        if self.timer.as_mut().poll(cx).is_pending(){
+            return Poll::Pending;
+        }
+        let (since, parties) = self.silent();
+        Poll::Ready(NetworkError::Timeout { timeout, since, parties })
+    }
+
+    /// Parties that went silent first, with the last round they were heard of, `None` if they never were
+    pub fn silent(&self) -> (Option<u16>, Vec<u16>){
+        let since = self.last_heard.values().min().copied().flatten();
+        let parties = self.last_heard.iter().filter(|(_, last)| **last == since).map(|(party, _)| *party).collect();
+        (since, parties)
+    }
+}
+
+#[cfg(test)]
+mod deadline_tests {
+    use futures::future::poll_fn;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_silent_parties_are_the_ones_left_behind() {
+        let mut deadline = Deadline::new(SessionTimeouts::default(), 1, &Participants::new(&[0, 1, 3]));
+        assert_eq!(deadline.silent(), (None, vec![0, 3]));
+
+        deadline.heard(3, 1);
+        deadline.heard(1, 4);
+        assert_eq!(deadline.silent(), (None, vec![0]));
+        deadline.heard(0, 2);
+        deadline.heard(3, 2);
+        assert_eq!(deadline.silent(), (Some(2), vec![0, 3]));
+        // Late messages of an earlier round do not move a party back
+        deadline.heard(3, 3);
+        deadline.heard(3, 1);
+        assert_eq!(deadline.silent(), (Some(2), vec![0]));
+    }
+
+    #[tokio::test]
+    async fn test_waiting_longer_than_the_round_timeout_expires() {
+        let timeouts = SessionTimeouts { round: Duration::from_millis(50), session: Duration::from_secs(60) };
+        let mut deadline = Deadline::new(timeouts, 0, &Participants::all(3));
+        deadline.heard(1, 1);
+
+        let error = tokio::time::timeout(Duration::from_secs(5), poll_fn(|cx| deadline.poll_expired(cx))).await.unwrap();
+        assert!(matches!(error, NetworkError::Timeout { timeout: Timeout::Round, since: None, parties } if parties == vec![2]));
+
+        let timeouts = SessionTimeouts { round: Duration::from_secs(60), session: Duration::from_millis(50) };
+        let mut deadline = Deadline::new(timeouts, 0, &Participants::all(2));
+        let error = tokio::time::timeout(Duration::from_secs(5), poll_fn(|cx| deadline.poll_expired(cx))).await.unwrap();
+        assert!(matches!(error, NetworkError::Timeout { timeout: Timeout::Session, since: None, parties } if parties == vec![1]));
+    }
+}
@@ -0,0 +1,193 @@
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+use libp2p::{swarm::{dial_opts::{DialOpts, PeerCondition}, SwarmEvent}, Multiaddr, PeerId, Swarm};
+
+use super::{behaviour::MyBehaviour, registry::PeerRegistry, setup::NetworkOptions};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+enum DialState{
+    /// Waiting until the instant before dialing again, after `attempts` failed dials in a row
+    Waiting{ attempts: u32, at: Instant },
+    Dialing{ attempts: u32 },
+    Connected,
+}
+
+/// Dials the static addresses of the registry peers, and dials them again with backoff when the
+/// dial fails or the connection drops. Peers without addresses are left to mDNS.
+pub struct Dialer{
+    options: NetworkOptions,
+    addrs: HashMap<PeerId, Vec<Multiaddr>>,
+    states: HashMap<PeerId, DialState>,
+}
+
+impl Dialer{
+    pub fn new(registry: &PeerRegistry, local_party_id: u16, options: &NetworkOptions, now: Instant) -> Dialer{
+        let addrs: HashMap<PeerId, Vec<Multiaddr>> = registry.peers()
+            .filter(|peer| peer.party_index != local_party_id)
+            .map(|peer| (peer.peer_id, peer.addrs.iter().filter(|addr| options.allows(addr)).cloned().collect::<Vec<_>>()))
+            .filter(|(_, addrs)| !addrs.is_empty())
+            .collect();
+        let states = addrs.keys()
+            .map(|peer_id| (*peer_id, DialState::Waiting { attempts: 0, at: now }))
+            .collect();
+        Dialer { options: options.clone(), addrs, states }
+    }
+
+    /// Whether an address, e.g. one discovered over mDNS, uses an enabled transport
+    pub fn allows(&self, addr: &Multiaddr) -> bool{
+        self.options.allows(addr)
+    }
+
+    /// Peers to dial now, they are considered dialing until the outcome is reported
+    pub fn due(&mut self, now: Instant) -> Vec<(PeerId, Vec<Multiaddr>)>{
+        let mut due = vec![];
+        for (peer_id, state) in self.states.iter_mut(){
+            if let DialState::Waiting { attempts, at } = *state && at <= now{
+                *state = DialState::Dialing { attempts };
+                due.push((*peer_id, self.addrs[peer_id].clone()));
+            }
+        }
+        due
+    }
+
+    pub fn connected(&mut self, peer_id: &PeerId){
+        if let Some(state) = self.states.get_mut(peer_id){
+            *state = DialState::Connected;
+        }
+    }
+
+    /// The last connection to the peer closed, it is dialed again shortly
+    pub fn disconnected(&mut self, peer_id: &PeerId, now: Instant){
+        if let Some(state) = self.states.get_mut(peer_id){
+            *state = DialState::Waiting { attempts: 0, at: now + INITIAL_BACKOFF };
+        }
+    }
+
+    /// Only failures of our own dials count, gossipsub also dials explicit peers by id and fails without addresses
+    pub fn dial_failed(&mut self, peer_id: &PeerId, now: Instant){
+        if let Some(state) = self.states.get_mut(peer_id)
+            && let DialState::Dialing { attempts } = *state{
+            *state = DialState::Waiting { attempts: attempts + 1, at: now + backoff(attempts + 1) };
+        }
+    }
+
+    /// Dials the peers that are due on all their static addresses
+    pub fn dial_due(&mut self, swarm: &mut Swarm<MyBehaviour>, now: Instant){
+        for (peer_id, addrs) in self.due(now){
+            swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+            let opts = DialOpts::peer_id(peer_id)
+                .addresses(addrs)
+                .condition(PeerCondition::DisconnectedAndNotDialing)
+                .build();
+            if let Err(e) = swarm.dial(opts){
+                if swarm.is_connected(&peer_id){
+                    self.connected(&peer_id);
+                }else{
+                    println!("Cannot dial {}: {}", peer_id, e);
+                    self.dial_failed(&peer_id, now);
+                }
+            }
+        }
+    }
+
+    /// Keeps track of the connections to the peers
+    pub fn on_swarm_event<E>(&mut self, event: &SwarmEvent<E>, now: Instant){
+        match event{
+            SwarmEvent::ConnectionEstablished { peer_id, .. } => self.connected(peer_id),
+            SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => self.disconnected(peer_id, now),
+            SwarmEvent::OutgoingConnectionError { peer_id: Some(peer_id), error, .. } => {
+                if matches!(self.states.get(peer_id), Some(DialState::Dialing { .. })){
+                    println!("Cannot connect to {}: {}", peer_id, error);
+                }
+                self.dial_failed(peer_id, now);
+            }
+            _ => {}
+        }
+    }
+
+    /// Peers that neither have a static address nor can be discovered
+    pub fn undialable(&self, registry: &PeerRegistry, local_party_id: u16) -> Vec<u16>{
+        registry.peers()
+            .filter(|peer| peer.party_index != local_party_id && !self.addrs.contains_key(&peer.peer_id))
+            .map(|peer| peer.party_index)
+            .collect()
+    }
+}
+
+fn backoff(attempts: u32) -> Duration{
+    INITIAL_BACKOFF.saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1))).min(MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod dialer_tests {
+    use libp2p::identity::Keypair;
+
+    use super::*;
+    use crate::off_chain::network::setup::Transport;
+
+    fn registry() -> (PeerRegistry, Vec<PeerId>){
+        let keypairs: Vec<Keypair> = (0..3).map(|_| Keypair::generate_ed25519()).collect();
+        let peers: Vec<_> = keypairs.iter().enumerate().map(|(i, keypair)| serde_json::json!({
+            "party_index": i,
+            "peer_id": keypair.public().to_peer_id().to_string(),
+            // Party 2 only has a QUIC address
+            "addrs": if i < 2 { vec![format!("/ip4/127.0.0.1/tcp/{}", 4000 + i)] } else { vec![format!("/ip4/127.0.0.1/udp/{}/quic-v1", 4000 + i)] },
+        })).collect();
+        let registry = PeerRegistry::from_json(&serde_json::json!({ "peers": peers }).to_string()).unwrap();
+        (registry, keypairs.iter().map(|keypair| keypair.public().to_peer_id()).collect())
+    }
+
+    #[test]
+    fn test_peers_are_dialed_with_backoff() {
+        let (registry, peer_ids) = registry();
+        let now = Instant::now();
+        let options = NetworkOptions { transports: vec![Transport::Tcp], ..Default::default() };
+        let mut dialer = Dialer::new(&registry, 0, &options, now);
+        assert_eq!(dialer.undialable(&registry, 0), vec![2]);
+
+        let due = dialer.due(now);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].0, peer_ids[1]);
+        assert!(dialer.due(now).is_empty());
+
+        dialer.dial_failed(&peer_ids[1], now);
+        // Failures of dials we did not start are ignored
+        dialer.dial_failed(&peer_ids[1], now);
+        assert!(dialer.due(now + INITIAL_BACKOFF / 2).is_empty());
+        assert_eq!(dialer.due(now + INITIAL_BACKOFF).len(), 1);
+
+        dialer.dial_failed(&peer_ids[1], now);
+        assert!(dialer.due(now + INITIAL_BACKOFF).is_empty());
+        assert_eq!(dialer.due(now + INITIAL_BACKOFF * 2).len(), 1);
+
+        dialer.connected(&peer_ids[1]);
+        assert!(dialer.due(now + MAX_BACKOFF).is_empty());
+
+        dialer.disconnected(&peer_ids[1], now);
+        assert_eq!(dialer.due(now + INITIAL_BACKOFF).len(), 1);
+    }
+
+    #[test]
+    fn test_addresses_are_filtered_by_transport() {
+        let (registry, peer_ids) = registry();
+        let now = Instant::now();
+        let options = NetworkOptions { transports: vec![Transport::Quic], ..Default::default() };
+        let mut dialer = Dialer::new(&registry, 0, &options, now);
+
+        let due = dialer.due(now);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].0, peer_ids[2]);
+        assert!(dialer.allows(&"/ip4/10.0.0.1/udp/4000/quic-v1".parse().unwrap()));
+        assert!(!dialer.allows(&"/ip4/10.0.0.1/tcp/4000".parse().unwrap()));
+        assert!(!dialer.allows(&"/ip4/10.0.0.1/udp/4000".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        assert_eq!(backoff(1), INITIAL_BACKOFF);
+        assert_eq!(backoff(3), INITIAL_BACKOFF * 4);
+        assert_eq!(backoff(40), MAX_BACKOFF);
+    }
+}
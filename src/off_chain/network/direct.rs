@@ -0,0 +1,221 @@
+use std::{collections::{HashMap, HashSet, VecDeque}, io, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::{request_response::{self, OutboundRequestId, ProtocolSupport}, PeerId, StreamProtocol, Swarm};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{behaviour::MyBehaviour, driver::Outcome, error::NetworkError};
+
+/// Protocol of the point-to-point channel, P2P protocol messages go straight to the recipient instead of gossipsub
+pub const DIRECT_PROTOCOL: StreamProtocol = StreamProtocol::new("/cggmp21/direct/1.0.0");
+
+// Same bound as gossipsub messages
+const MAX_MESSAGE_SIZE: u64 = 4*1024*1024;
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+// Aux info messages are large and the recipient may be busy proving, the ack can take a while
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+// Digests of delivered messages kept to drop retransmissions
+const DELIVERED_CAPACITY: usize = 4096;
+
+/// Answer of the recipient once it has queued a direct message for its session
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirectAck{
+    Received,
+    /// The message cannot be routed, sending it again will not help
+    Rejected,
+}
+
+/// Direct messages are serialized `Envelope`s, one per stream, acknowledged by a [`DirectAck`]
+#[derive(Clone, Default)]
+pub struct DirectCodec;
+
+#[async_trait]
+impl request_response::Codec for DirectCodec{
+    type Protocol = StreamProtocol;
+    type Request = Vec<u8>;
+    type Response = DirectAck;
+
+    async fn read_request<T>(&mut self, _protocol: &StreamProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where T: AsyncRead + Unpin + Send
+    {
+        let mut request = vec![];
+        io.take(MAX_MESSAGE_SIZE + 1).read_to_end(&mut request).await?;
+        if request.len() as u64 > MAX_MESSAGE_SIZE{
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "direct message too large"));
+        }
+        Ok(request)
+    }
+
+    async fn read_response<T>(&mut self, _protocol: &StreamProtocol, io: &mut T) -> io::Result<DirectAck>
+    where T: AsyncRead + Unpin + Send
+    {
+        let mut response = vec![];
+        io.take(16).read_to_end(&mut response).await?;
+        bincode::deserialize(&response).map_err(io::Error::other)
+    }
+
+    async fn write_request<T>(&mut self, _protocol: &StreamProtocol, io: &mut T, request: Vec<u8>) -> io::Result<()>
+    where T: AsyncWrite + Unpin + Send
+    {
+        io.write_all(&request).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(&mut self, _protocol: &StreamProtocol, io: &mut T, response: DirectAck) -> io::Result<()>
+    where T: AsyncWrite + Unpin + Send
+    {
+        io.write_all(&bincode::serialize(&response).map_err(io::Error::other)?).await?;
+        io.close().await
+    }
+}
+
+pub fn behaviour() -> request_response::Behaviour<DirectCodec>{
+    request_response::Behaviour::with_codec(
+        DirectCodec,
+        [(DIRECT_PROTOCOL, ProtocolSupport::Full)],
+        request_response::Config::default().with_request_timeout(REQUEST_TIMEOUT),
+    )
+}
+
+/// Remembers the messages already delivered, a message sent again because its ack was lost is acknowledged
+/// but not routed twice
+///
+/// Only messages routed to a session are remembered, a rejected one sent again is checked and rejected again.
+#[derive(Default)]
+pub struct DirectInbox{
+    delivered: HashSet<[u8; 32]>,
+    order: VecDeque<[u8; 32]>,
+}
+
+impl DirectInbox{
+    /// Whether the message was already routed, retransmissions are byte for byte identical
+    pub fn already_delivered(&self, request: &[u8]) -> bool{
+        self.delivered.contains(&Self::digest(request))
+    }
+
+    /// Records a message routed to its session
+    pub fn delivered(&mut self, request: &[u8]){
+        let digest = Self::digest(request);
+        if !self.delivered.insert(digest){
+            return;
+        }
+        self.order.push_back(digest);
+        if self.order.len() > DELIVERED_CAPACITY
+            && let Some(oldest) = self.order.pop_front(){
+            self.delivered.remove(&oldest);
+        }
+    }
+
+    fn digest(request: &[u8]) -> [u8; 32]{
+        Sha256::digest(request).into()
+    }
+}
+
+struct PendingMessage{
+    party: u16,
+    peer_id: PeerId,
+    data: Vec<u8>,
+    attempts: u32,
+    outcome: Outcome,
+}
+
+/// Direct messages waiting for their acknowledgment, sent again a few times when the request fails
+///
+/// The sender learns the outcome once the message is acknowledged or given up.
+#[derive(Default)]
+pub struct DirectOutbox{
+    pending: HashMap<OutboundRequestId, PendingMessage>,
+    retries: Vec<(Instant, PendingMessage)>,
+}
+
+impl DirectOutbox{
+    pub fn send(&mut self, swarm: &mut Swarm<MyBehaviour>, party: u16, peer_id: PeerId, data: Vec<u8>, outcome: Outcome){
+        self.send_attempt(swarm, PendingMessage { party, peer_id, data, attempts: 1, outcome });
+    }
+
+    fn send_attempt(&mut self, swarm: &mut Swarm<MyBehaviour>, message: PendingMessage){
+        let request_id = swarm.behaviour_mut().direct.send_request(&message.peer_id, message.data.clone());
+        self.pending.insert(request_id, message);
+    }
+
+    pub fn acknowledged(&mut self, request_id: &OutboundRequestId, ack: DirectAck){
+        let Some(message) = self.pending.remove(request_id) else {
+            return;
+        };
+        let result = match ack{
+            DirectAck::Received => Ok(()),
+            DirectAck::Rejected => {
+                println!("Direct message to {} was rejected", message.peer_id);
+                Err(NetworkError::Undelivered { party: message.party, reason: "rejected by the recipient".to_string() })
+            }
+        };
+        // The session may be gone already
+        let _ = message.outcome.send(result);
+    }
+
+    pub fn failed(&mut self, request_id: &OutboundRequestId, error: &request_response::OutboundFailure, now: Instant){
+        let Some(message) = self.pending.remove(request_id) else {
+            return;
+        };
+        if message.attempts >= MAX_ATTEMPTS{
+            println!("Giving up direct message to {} after {} attempts: {}", message.peer_id, message.attempts, error);
+            let _ = message.outcome.send(Err(NetworkError::Undelivered { party: message.party, reason: error.to_string() }));
+            return;
+        }
+        println!("Direct message to {} failed, sending it again: {}", message.peer_id, error);
+        self.retries.push((now + RETRY_DELAY, message));
+    }
+
+    /// Sends again the failed messages whose retry delay is over
+    pub fn retry_due(&mut self, swarm: &mut Swarm<MyBehaviour>, now: Instant){
+        let (due, waiting): (Vec<_>, Vec<_>) = self.retries.drain(..).partition(|(at, _)| *at <= now);
+        self.retries = waiting;
+        for (_, message) in due{
+            let attempts = message.attempts + 1;
+            self.send_attempt(swarm, PendingMessage { attempts, ..message });
+        }
+    }
+}
+
+#[cfg(test)]
+mod direct_codec_tests {
+    use futures::io::Cursor;
+    use request_response::Codec;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_requests_and_acks_round_trip() {
+        let mut codec = DirectCodec;
+
+        let mut io = Cursor::new(vec![]);
+        codec.write_request(&DIRECT_PROTOCOL, &mut io, b"session message".to_vec()).await.unwrap();
+        io.set_position(0);
+        assert_eq!(codec.read_request(&DIRECT_PROTOCOL, &mut io).await.unwrap(), b"session message");
+
+        let mut io = Cursor::new(vec![]);
+        codec.write_response(&DIRECT_PROTOCOL, &mut io, DirectAck::Rejected).await.unwrap();
+        io.set_position(0);
+        assert_eq!(codec.read_response(&DIRECT_PROTOCOL, &mut io).await.unwrap(), DirectAck::Rejected);
+    }
+
+    #[test]
+    fn test_retransmissions_are_delivered_once() {
+        let mut inbox = DirectInbox::default();
+
+        assert!(!inbox.already_delivered(b"round 1"));
+        inbox.delivered(b"round 1");
+        inbox.delivered(b"round 2");
+        assert!(inbox.already_delivered(b"round 1"));
+        // A rejected message is not recorded
+        assert!(!inbox.already_delivered(b"round 3"));
+
+        for i in 0..DELIVERED_CAPACITY{
+            inbox.delivered(&i.to_be_bytes());
+        }
+        assert!(!inbox.already_delivered(b"round 1"));
+    }
+}
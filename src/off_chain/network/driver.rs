@@ -0,0 +1,276 @@
+use std::{collections::{HashSet, VecDeque}, future::Future, pin::Pin, sync::{Arc, Mutex}, task::{Context, Poll}, time::Instant};
+
+use cggmp21::round_based::MessageType;
+use futures::StreamExt;
+use libp2p::{gossipsub::{self, IdentTopic}, mdns, request_response::{self, ResponseChannel}, swarm::SwarmEvent, PeerId, Swarm};
+use tokio::sync::{mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender}, oneshot};
+
+use super::{behaviour::{MyBehaviour, MyBehaviourEvent}, dialer::Dialer, direct::{DirectAck, DirectInbox, DirectOutbox}, error::NetworkError, node::message_author, registry::PeerRegistry, envelope::Envelope, session::{RoutedMessage, SessionRouter}, setup::{NetworkSetup, DIAL_INTERVAL}};
+
+/// Tells a session whether its message went out
+pub type Outcome = oneshot::Sender<Result<(), NetworkError>>;
+
+/// Message of a session for the swarm driver to send, already serialized as an [`Envelope`]
+pub enum Command{
+    /// Published on the broadcast topic
+    Broadcast{ data: Vec<u8>, outcome: Outcome },
+    /// Sent to one party and acknowledged, see [`DirectOutbox`]
+    Direct{ party: u16, peer_id: PeerId, data: Vec<u8>, outcome: Outcome },
+}
+
+/// Sending side of a session, keeps track of the outcome of the messages it handed to the driver
+pub struct DriverHandle{
+    commands: UnboundedSender<Command>,
+    pending: VecDeque<oneshot::Receiver<Result<(), NetworkError>>>,
+}
+
+impl DriverHandle{
+    pub fn new(commands: UnboundedSender<Command>) -> DriverHandle{
+        DriverHandle { commands, pending: VecDeque::new() }
+    }
+
+    pub fn broadcast(&mut self, data: Vec<u8>) -> Result<(), NetworkError>{
+        self.send(|outcome| Command::Broadcast { data, outcome })
+    }
+
+    pub fn direct(&mut self, party: u16, peer_id: PeerId, data: Vec<u8>) -> Result<(), NetworkError>{
+        self.send(|outcome| Command::Direct { party, peer_id, data, outcome })
+    }
+
+    fn send(&mut self, command: impl FnOnce(Outcome) -> Command) -> Result<(), NetworkError>{
+        let (outcome, receiver) = oneshot::channel();
+        self.commands.send(command(outcome)).map_err(|_| NetworkError::Stopped)?;
+        self.pending.push_back(receiver);
+        Ok(())
+    }
+
+    /// Ready once every message went out, or with the first one that did not
+    pub fn poll_sent(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), NetworkError>>{
+        while let Some(receiver) = self.pending.front_mut(){
+            let result = match Pin::new(receiver).poll(cx){
+                Poll::Ready(result) => result,
+                Poll::Pending => return Poll::Pending,
+            };
+            self.pending.pop_front();
+            // The driver drops the outcome only when it stops
+            result.map_err(|_| NetworkError::Stopped)??;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Parties the node is connected to, kept up to date by the swarm driver
+#[derive(Default)]
+pub struct ConnectedParties(Mutex<HashSet<u16>>);
+
+impl ConnectedParties{
+    pub fn contains(&self, party: u16) -> bool{
+        self.0.lock().expect("Cannot lock connected parties").contains(&party)
+    }
+
+    fn insert(&self, party: u16){
+        self.0.lock().expect("Cannot lock connected parties").insert(party);
+    }
+
+    fn remove(&self, party: u16){
+        self.0.lock().expect("Cannot lock connected parties").remove(&party);
+    }
+}
+
+/// Event loop of the node, the only owner of the swarm
+///
+/// Sessions never touch the swarm: they hand their messages over a channel of [`Command`]s and receive theirs
+/// through the router, which feeds one channel per session. The loop redials peers, retries unacknowledged
+/// direct messages, and keeps polling the swarm whatever the sessions are doing.
+pub struct SwarmDriver{
+    swarm: Swarm<MyBehaviour>,
+    dialer: Dialer,
+    registry: Arc<PeerRegistry>,
+    router: Arc<Mutex<SessionRouter>>,
+    connected: Arc<ConnectedParties>,
+    outbox: DirectOutbox,
+    inbox: DirectInbox,
+    broadcast_topic: IdentTopic,
+    commands: UnboundedReceiver<Command>,
+}
+
+impl SwarmDriver{
+    /// Starts the event loop, it stops once every sender of commands is dropped
+    pub fn spawn(network_setup: NetworkSetup, registry: Arc<PeerRegistry>, router: Arc<Mutex<SessionRouter>>, connected: Arc<ConnectedParties>) -> UnboundedSender<Command>{
+        let (sender, commands) = unbounded_channel();
+        // Parties that were not up in time are connected to later, see NetworkSetup::setup_swarm
+        for party in network_setup.swarm.connected_peers().filter_map(|peer_id| registry.party_index(peer_id)){
+            connected.insert(party);
+        }
+        let driver = SwarmDriver{
+            swarm: network_setup.swarm,
+            dialer: network_setup.dialer,
+            registry,
+            router,
+            connected,
+            outbox: DirectOutbox::default(),
+            inbox: DirectInbox::default(),
+            broadcast_topic: network_setup.broadcast_topic,
+            commands,
+        };
+        tokio::spawn(driver.run());
+        sender
+    }
+
+    async fn run(mut self){
+        let mut dial_tick = tokio::time::interval(DIAL_INTERVAL);
+        loop{
+            tokio::select!{
+                event = self.swarm.select_next_some() => self.on_swarm_event(event),
+                command = self.commands.recv() => match command{
+                    Some(command) => self.execute(command),
+                    None => return,
+                },
+                _ = dial_tick.tick() => {
+                    self.dialer.dial_due(&mut self.swarm, Instant::now());
+                    self.outbox.retry_due(&mut self.swarm, Instant::now());
+                }
+            }
+        }
+    }
+
+    fn execute(&mut self, command: Command){
+        match command{
+            Command::Broadcast { data, outcome } => {
+                let result = self.swarm.behaviour_mut().gossipsub.publish(self.broadcast_topic.clone(), data);
+                if let Err(e) = &result{
+                    println!("Cannot publish broadcast: {}", e);
+                }
+                let _ = outcome.send(result.map(|_| ()).map_err(NetworkError::from));
+            }
+            Command::Direct { party, peer_id, data, outcome } => self.outbox.send(&mut self.swarm, party, peer_id, data, outcome),
+        }
+    }
+
+    fn on_swarm_event(&mut self, event: SwarmEvent<MyBehaviourEvent>){
+        self.dialer.on_swarm_event(&event, Instant::now());
+        match event{
+            SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Message { propagation_source, message, .. })) => {
+                self.route_broadcast(propagation_source, message);
+            }
+            SwarmEvent::Behaviour(MyBehaviourEvent::Direct(request_response::Event::Message { peer, message, .. })) => {
+                match message{
+                    request_response::Message::Request { request, channel, .. } => self.route_direct(peer, request, channel),
+                    request_response::Message::Response { request_id, response } => self.outbox.acknowledged(&request_id, response),
+                }
+            }
+            SwarmEvent::Behaviour(MyBehaviourEvent::Direct(request_response::Event::OutboundFailure { request_id, error, .. })) => {
+                self.outbox.failed(&request_id, &error, Instant::now());
+            }
+            SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
+                for (peer_id, addr) in peers.into_iter().filter(|(peer_id, addr)| self.registry.party_index(peer_id).is_some() && self.dialer.allows(addr)){
+                    if !self.swarm.is_connected(&peer_id){
+                        self.swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+                        if let Err(e) = self.swarm.dial(addr.clone()){
+                            println!("Cannot dial {} on address {}: {}", peer_id, addr, e);
+                        }
+                    }
+                }
+            }
+            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                if let Some(party) = self.registry.party_index(&peer_id){
+                    self.connected.insert(party);
+                }
+            }
+            SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => {
+                println!("Connection to {} closed", peer_id);
+                if let Some(party) = self.registry.party_index(&peer_id){
+                    self.connected.remove(party);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn route_broadcast(&self, propagation_source: PeerId, message: gossipsub::Message){
+        if message.topic != self.broadcast_topic.hash(){
+            println!("Wrong message type");
+            return;
+        }
+
+        let Some(author) = message_author(&self.registry, &propagation_source, &message) else {
+            return;
+        };
+
+        if let Some(envelope) = self.open_envelope(author, &message.data){
+            self.router.lock().expect("Cannot lock router").route(RoutedMessage { msg_type: MessageType::Broadcast, envelope });
+        }
+    }
+
+    /// Queues a P2P message for its session and acknowledges it, the peer is authenticated by the connection
+    fn route_direct(&mut self, peer: PeerId, request: Vec<u8>, channel: ResponseChannel<DirectAck>){
+        if self.inbox.already_delivered(&request){
+            println!("Direct message from {} already delivered", peer);
+            self.acknowledge(peer, channel, DirectAck::Received);
+            return;
+        }
+
+        let envelope = match self.registry.party_index(&peer){
+            Some(author) => self.open_envelope(author, &request),
+            None => {
+                println!("Direct message from {}, not a party", peer);
+                None
+            }
+        };
+
+        let ack = if envelope.is_some() { DirectAck::Received } else { DirectAck::Rejected };
+        if let Some(envelope) = envelope{
+            self.router.lock().expect("Cannot lock router").route(RoutedMessage { msg_type: MessageType::P2P, envelope });
+            self.inbox.delivered(&request);
+        }
+        self.acknowledge(peer, channel, ack);
+    }
+
+    /// Envelope written by `author`, the rest of it is checked by its session
+    fn open_envelope(&self, author: u16, data: &[u8]) -> Option<Envelope>{
+        let Ok(envelope) = bincode::deserialize::<Envelope>(data) else {
+            println!("Cannot deserialize envelope from party {}", author);
+            return None;
+        };
+        if envelope.sender != author{
+            println!("Party {} sent an envelope as party {}", author, envelope.sender);
+            return None;
+        }
+        Some(envelope)
+    }
+
+    fn acknowledge(&mut self, peer: PeerId, channel: ResponseChannel<DirectAck>, ack: DirectAck){
+        if self.swarm.behaviour_mut().direct.send_response(channel, ack).is_err(){
+            println!("Cannot acknowledge direct message from {}", peer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod driver_handle_tests {
+    use futures::{future::poll_fn, FutureExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_outcomes_are_reported_to_the_sender() {
+        let (commands, mut driver) = unbounded_channel();
+        let mut handle = DriverHandle::new(commands);
+
+        handle.broadcast(b"round 1".to_vec()).unwrap();
+        handle.direct(2, PeerId::random(), b"share".to_vec()).unwrap();
+        assert!(poll_fn(|cx| handle.poll_sent(cx)).now_or_never().is_none());
+
+        let Some(Command::Broadcast { outcome, .. }) = driver.recv().await else { panic!("expected a broadcast") };
+        outcome.send(Ok(())).unwrap();
+        let Some(Command::Direct { party: 2, outcome, .. }) = driver.recv().await else { panic!("expected a direct message") };
+        outcome.send(Err(NetworkError::Undelivered { party: 2, reason: "timeout".to_string() })).unwrap();
+        assert!(matches!(poll_fn(|cx| handle.poll_sent(cx)).await, Err(NetworkError::Undelivered { party: 2, .. })));
+        assert!(poll_fn(|cx| handle.poll_sent(cx)).await.is_ok());
+
+        handle.broadcast(b"round 2".to_vec()).unwrap();
+        drop(driver);
+        assert!(matches!(poll_fn(|cx| handle.poll_sent(cx)).await, Err(NetworkError::Stopped)));
+        assert!(matches!(handle.broadcast(b"round 3".to_vec()), Err(NetworkError::Stopped)));
+    }
+}
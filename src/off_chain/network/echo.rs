@@ -0,0 +1,194 @@
+use std::{collections::HashMap, error::Error, fmt};
+
+use cggmp21::round_based::MsgId;
+use sha2::{Digest, Sha256};
+
+use super::session::{Participants, SessionId};
+
+#[derive(Debug)]
+pub enum EchoError{
+    /// `sender` broadcast a round message that `witness` saw differently
+    Equivocation{ sender: u16, round: u16, witness: u16 },
+    /// `witness` echoed two different digests for the same message
+    ConflictingEchoes{ sender: u16, round: u16, witness: u16 },
+}
+
+impl fmt::Display for EchoError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self{
+            EchoError::Equivocation { sender, round, witness } => write!(f, "party {} equivocated in round {}, party {} received another message", sender, round, witness),
+            EchoError::ConflictingEchoes { sender, round, witness } => write!(f, "party {} echoed two messages of party {} for round {}", witness, sender, round),
+        }
+    }
+}
+
+impl Error for EchoError{}
+
+/// Broadcast message held until every other participant confirmed receiving the same one
+pub struct Delivery{
+    pub id: MsgId,
+    pub sender: u16,
+    pub round: u16,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Entry{
+    /// Digest of the message received from the sender, kept after delivery to catch a late conflicting one
+    digest: Option<[u8; 32]>,
+    /// Message waiting for its echoes
+    pending: Option<(MsgId, Vec<u8>)>,
+    echoes: HashMap<u16, [u8; 32]>,
+}
+
+/// Echo broadcast over gossipsub, the reliable broadcast cggmp21 assumes for keygen and aux info
+///
+/// Every participant echoes the digest of each broadcast message it receives to the others. A message is
+/// handed to the protocol once all the participants but its sender echoed the same digest, so all honest
+/// parties go on with the same message. A sender publishing different messages to different parties is
+/// caught as soon as one echo does not match, and the run is aborted. Parties are keygen indexes.
+pub struct EchoBroadcast{
+    session_id: SessionId,
+    local_party_id: u16,
+    participants: Participants,
+    entries: HashMap<(u16, u16), Entry>,
+}
+
+impl EchoBroadcast{
+    pub fn new(session_id: SessionId, local_party_id: u16, participants: Participants) -> EchoBroadcast{
+        EchoBroadcast { session_id, local_party_id, participants, entries: HashMap::new() }
+    }
+
+    fn digest(&self, sender: u16, round: u16, payload: &[u8]) -> [u8; 32]{
+        let mut hasher = Sha256::new();
+        hasher.update(b"cggmp21/echo");
+        hasher.update(bincode::serialize(&self.session_id).expect("Cannot serialize session id"));
+        hasher.update(sender.to_be_bytes());
+        hasher.update(round.to_be_bytes());
+        hasher.update(payload);
+        hasher.finalize().into()
+    }
+
+    /// Records a broadcast message of `sender`, returns the digest to echo the first time it is received
+    pub fn message(&mut self, id: MsgId, sender: u16, round: u16, payload: Vec<u8>) -> Result<Option<[u8; 32]>, EchoError>{
+        let digest = self.digest(sender, round, &payload);
+        let local_party_id = self.local_party_id;
+        let entry = self.entries.entry((sender, round)).or_default();
+
+        if let Some(received) = entry.digest{
+            if received != digest{
+                return Err(EchoError::Equivocation { sender, round, witness: local_party_id });
+            }
+            return Ok(None);
+        }
+        if let Some((witness, _)) = entry.echoes.iter().find(|(_, echoed)| **echoed != digest){
+            return Err(EchoError::Equivocation { sender, round, witness: *witness });
+        }
+        entry.digest = Some(digest);
+        entry.pending = Some((id, payload));
+        Ok(Some(digest))
+    }
+
+    /// Records the digest `witness` received from `sender` for `round`
+    pub fn echo(&mut self, witness: u16, sender: u16, round: u16, digest: [u8; 32]) -> Result<(), EchoError>{
+        // Only the parties that take part in the run vouch for its messages
+        if witness == sender || self.participants.protocol_index(sender).is_none(){
+            return Ok(());
+        }
+        let entry = self.entries.entry((sender, round)).or_default();
+
+        if let Some(echoed) = entry.echoes.insert(witness, digest) && echoed != digest{
+            return Err(EchoError::ConflictingEchoes { sender, round, witness });
+        }
+        match entry.digest{
+            Some(received) if received != digest => Err(EchoError::Equivocation { sender, round, witness }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Next message echoed by all the other participants, each one is delivered once
+    pub fn ready(&mut self) -> Option<Delivery>{
+        let local_party_id = self.local_party_id;
+        let participants = &self.participants;
+        let ((sender, round), entry) = self.entries.iter_mut().find(|((sender, _), entry)| {
+            entry.pending.is_some()
+                && participants.parties().iter()
+                    .filter(|party| **party != *sender && **party != local_party_id)
+                    .all(|party| entry.echoes.contains_key(party))
+        })?;
+
+        let (id, payload) = entry.pending.take().expect("Ready message is pending");
+        Some(Delivery { id, sender: *sender, round: *round, payload })
+    }
+}
+
+#[cfg(test)]
+mod echo_broadcast_tests {
+    use super::*;
+    use crate::off_chain::network::session::ProtocolKind;
+
+    fn echo_broadcast(local_party_id: u16) -> EchoBroadcast{
+        EchoBroadcast::new(SessionId::new(ProtocolKind::Keygen, b"exec"), local_party_id, Participants::all(4))
+    }
+
+    #[test]
+    fn test_message_is_delivered_once_echoed_by_all() {
+        let mut party = echo_broadcast(0);
+
+        let digest = party.message(7, 1, 1, b"commitment".to_vec()).unwrap().unwrap();
+        // Receiving the same message again is not echoed twice
+        assert!(party.message(7, 1, 1, b"commitment".to_vec()).unwrap().is_none());
+        party.echo(2, 1, 1, digest).unwrap();
+        assert!(party.ready().is_none());
+        // The sender does not vouch for its own message
+        party.echo(1, 1, 1, [0; 32]).unwrap();
+        assert!(party.ready().is_none());
+
+        party.echo(3, 1, 1, digest).unwrap();
+        let delivery = party.ready().unwrap();
+        assert_eq!((delivery.id, delivery.sender, delivery.round, delivery.payload), (7, 1, 1, b"commitment".to_vec()));
+        assert!(party.ready().is_none());
+    }
+
+    #[test]
+    fn test_echoes_may_come_before_the_message() {
+        let mut sender_view = echo_broadcast(2);
+        let digest = sender_view.message(1, 0, 2, b"decommitment".to_vec()).unwrap().unwrap();
+
+        let mut party = echo_broadcast(3);
+        party.echo(1, 0, 2, digest).unwrap();
+        party.echo(2, 0, 2, digest).unwrap();
+        assert!(party.ready().is_none());
+        party.message(4, 0, 2, b"decommitment".to_vec()).unwrap();
+        assert_eq!(party.ready().unwrap().sender, 0);
+    }
+
+    #[test]
+    fn test_only_participants_are_waited_for() {
+        // Run between parties 0, 1 and 3, party 2 never opens the session
+        let mut party = EchoBroadcast::new(SessionId::new(ProtocolKind::AuxInfo, b"exec"), 0, Participants::new(&[0, 1, 3]));
+        let digest = party.message(2, 1, 1, b"commitment".to_vec()).unwrap().unwrap();
+        party.echo(3, 1, 1, digest).unwrap();
+        assert_eq!(party.ready().unwrap().sender, 1);
+
+        // Between two parties there is nobody left to echo
+        let mut party = EchoBroadcast::new(SessionId::new(ProtocolKind::AuxInfo, b"exec"), 0, Participants::new(&[2, 0]));
+        party.message(0, 2, 1, b"commitment".to_vec()).unwrap();
+        assert_eq!(party.ready().unwrap().sender, 2);
+    }
+
+    #[test]
+    fn test_equivocation_aborts() {
+        let mut witness = echo_broadcast(2);
+        let digest = witness.message(1, 1, 1, b"to party 2".to_vec()).unwrap().unwrap();
+
+        let mut party = echo_broadcast(0);
+        party.message(1, 1, 1, b"to party 0".to_vec()).unwrap();
+        assert!(matches!(party.echo(2, 1, 1, digest), Err(EchoError::Equivocation { sender: 1, round: 1, witness: 2 })));
+
+        let mut party = echo_broadcast(0);
+        party.echo(2, 1, 1, digest).unwrap();
+        assert!(matches!(party.message(1, 1, 1, b"to party 0".to_vec()), Err(EchoError::Equivocation { sender: 1, round: 1, witness: 2 })));
+        assert!(matches!(party.echo(2, 1, 1, [0; 32]), Err(EchoError::ConflictingEchoes { sender: 1, round: 1, witness: 2 })));
+    }
+}
@@ -0,0 +1,153 @@
+use std::{collections::HashSet, sync::{atomic::{AtomicU64, Ordering}, Arc}};
+
+use cggmp21::round_based::MessageType;
+use serde::{Deserialize, Serialize};
+
+use super::{error::NetworkError, session::SessionId};
+
+/// Version of the envelope layout and of the rules the parties follow to exchange messages
+pub const PROTOCOL_VERSION: u16 = 2;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Payload{
+    /// Protocol message broadcast to every participant
+    Broadcast(Vec<u8>),
+    /// Protocol message encrypted to the recipient, see [`super::channel`]
+    Sealed(Vec<u8>),
+    /// Digest of the broadcast `sender` made for the round, see [`super::echo`]
+    Echo{ sender: u16, digest: [u8; 32] },
+    /// The sender gave up the run, the other participants stop waiting for it
+    Abort(String),
+}
+
+/// Wire format of every message of a session, on gossipsub and on the direct channel
+///
+/// Parties are keygen indexes. The sender numbers its envelopes from 0 in each session, an envelope
+/// received twice is a replay.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Envelope{
+    pub version: u16,
+    pub session_id: SessionId,
+    pub round: u16,
+    pub sender: u16,
+    /// Recipient of a P2P message, `None` for broadcasts
+    pub recipient: Option<u16>,
+    pub msg_id: u64,
+    pub payload: Payload,
+}
+
+/// Writes the envelopes of the local party in a session, its sink and stream share the numbering
+#[derive(Clone)]
+pub struct EnvelopeWriter{
+    session_id: SessionId,
+    sender: u16,
+    next_msg_id: Arc<AtomicU64>,
+}
+
+impl EnvelopeWriter{
+    pub fn new(session_id: SessionId, sender: u16) -> EnvelopeWriter{
+        EnvelopeWriter { session_id, sender, next_msg_id: Arc::new(AtomicU64::new(0)) }
+    }
+
+    pub fn write(&self, round: u16, recipient: Option<u16>, payload: Payload) -> Result<Vec<u8>, NetworkError>{
+        let envelope = Envelope{
+            version: PROTOCOL_VERSION,
+            session_id: self.session_id,
+            round,
+            sender: self.sender,
+            recipient,
+            msg_id: self.next_msg_id.fetch_add(1, Ordering::Relaxed),
+            payload,
+        };
+        Ok(bincode::serialize(&envelope)?)
+    }
+}
+
+/// Checks the envelopes a session receives, before their payload is looked at
+pub struct EnvelopeCheck{
+    session_id: SessionId,
+    local_party_id: u16,
+    seen: HashSet<(u16, u64)>,
+}
+
+impl EnvelopeCheck{
+    pub fn new(session_id: SessionId, local_party_id: u16) -> EnvelopeCheck{
+        EnvelopeCheck { session_id, local_party_id, seen: HashSet::new() }
+    }
+
+    /// `msg_type` is how the envelope arrived, on gossipsub or directly from its sender
+    pub fn check(&mut self, msg_type: MessageType, envelope: &Envelope) -> Result<(), NetworkError>{
+        let sender = envelope.sender;
+        if envelope.version != PROTOCOL_VERSION{
+            return Err(NetworkError::UnsupportedVersion { sender, version: envelope.version });
+        }
+        if envelope.session_id != self.session_id{
+            return Err(NetworkError::WrongSession { sender });
+        }
+        let well_formed = match (msg_type, envelope.recipient, &envelope.payload){
+            (MessageType::Broadcast, None, Payload::Broadcast(_) | Payload::Abort(_)) => true,
+            (MessageType::Broadcast, None, Payload::Echo { .. }) => self.session_id.kind.echoes_broadcasts(),
+            (MessageType::P2P, Some(recipient), Payload::Sealed(_)) => recipient == self.local_party_id,
+            _ => false,
+        };
+        if !well_formed{
+            return Err(NetworkError::Malformed { sender });
+        }
+        if !self.seen.insert((sender, envelope.msg_id)){
+            return Err(NetworkError::Replayed { sender, msg_id: envelope.msg_id });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod envelope_tests {
+    use super::*;
+    use crate::off_chain::network::session::ProtocolKind;
+
+    fn read(data: &[u8]) -> Envelope{
+        bincode::deserialize(data).unwrap()
+    }
+
+    #[test]
+    fn test_envelopes_are_numbered_per_session() {
+        let session_id = SessionId::new(ProtocolKind::AuxInfo, b"exec");
+        let writer = EnvelopeWriter::new(session_id, 2);
+        let stream_writer = writer.clone();
+
+        let first = read(&writer.write(1, None, Payload::Broadcast(b"commitment".to_vec())).unwrap());
+        let second = read(&stream_writer.write(1, None, Payload::Echo { sender: 0, digest: [7; 32] }).unwrap());
+        let third = read(&writer.write(3, Some(1), Payload::Sealed(b"share".to_vec())).unwrap());
+
+        assert_eq!((first.version, first.session_id, first.sender), (PROTOCOL_VERSION, session_id, 2));
+        assert_eq!((first.msg_id, second.msg_id, third.msg_id), (0, 1, 2));
+        assert_eq!((third.round, third.recipient), (3, Some(1)));
+    }
+
+    #[test]
+    fn test_invalid_envelopes_are_rejected() {
+        let session_id = SessionId::new(ProtocolKind::Keygen, b"exec");
+        let writer = EnvelopeWriter::new(session_id, 1);
+        let broadcast = read(&writer.write(1, None, Payload::Broadcast(b"commitment".to_vec())).unwrap());
+        let sealed = read(&writer.write(2, Some(0), Payload::Sealed(b"share".to_vec())).unwrap());
+        let mut check = EnvelopeCheck::new(session_id, 0);
+
+        check.check(MessageType::Broadcast, &broadcast).unwrap();
+        assert!(matches!(check.check(MessageType::Broadcast, &broadcast), Err(NetworkError::Replayed { sender: 1, msg_id: 0 })));
+        // A P2P message cannot be published, nor sent to another party
+        assert!(matches!(check.check(MessageType::Broadcast, &sealed), Err(NetworkError::Malformed { sender: 1 })));
+        assert!(matches!(EnvelopeCheck::new(session_id, 2).check(MessageType::P2P, &sealed), Err(NetworkError::Malformed { sender: 1 })));
+        check.check(MessageType::P2P, &sealed).unwrap();
+        let abort = read(&writer.write(0, Some(0), Payload::Abort("timeout".to_string())).unwrap());
+        assert!(matches!(check.check(MessageType::P2P, &abort), Err(NetworkError::Malformed { sender: 1 })));
+
+        let stale = Envelope { version: 0, msg_id: 5, ..broadcast.clone() };
+        assert!(matches!(check.check(MessageType::Broadcast, &stale), Err(NetworkError::UnsupportedVersion { sender: 1, version: 0 })));
+        // Only keygen and aux info broadcasts are echoed
+        let echo = read(&EnvelopeWriter::new(SessionId::new(ProtocolKind::Signing, b"exec"), 1).write(1, None, Payload::Echo { sender: 2, digest: [7; 32] }).unwrap());
+        assert!(matches!(EnvelopeCheck::new(echo.session_id, 0).check(MessageType::Broadcast, &echo), Err(NetworkError::Malformed { sender: 1 })));
+
+        let other_session = Envelope { session_id: SessionId::new(ProtocolKind::Signing, b"exec"), msg_id: 6, ..broadcast };
+        assert!(matches!(check.check(MessageType::Broadcast, &other_session), Err(NetworkError::WrongSession { sender: 1 })));
+    }
+}
@@ -0,0 +1,98 @@
+use std::{error::Error, fmt};
+
+use libp2p::gossipsub::PublishError;
+
+use super::{channel::ChannelError, deadline::Timeout, echo::EchoError, envelope::PROTOCOL_VERSION};
+
+/// Failure of the network under a protocol run, the run is aborted
+///
+/// It reaches cggmp21 as a delivery error, parties are keygen indexes.
+#[derive(Debug)]
+pub enum NetworkError{
+    /// gossipsub refused to publish a broadcast
+    Publish(PublishError),
+    /// No peer is subscribed to the broadcast topic, the message would reach nobody
+    InsufficientPeers,
+    /// A direct message was given up before the recipient acknowledged it
+    Undelivered{ party: u16, reason: String },
+    Serialization(bincode::Error),
+    Deserialization{ sender: u16, error: bincode::Error },
+    /// Envelope of `sender` for `round` whose message is of another round
+    RoundMismatch{ sender: u16, round: u16, actual: u16 },
+    UnsupportedVersion{ sender: u16, version: u16 },
+    /// Envelope of another session routed to this one
+    WrongSession{ sender: u16 },
+    /// Envelope whose recipient or payload does not match the way it was sent
+    Malformed{ sender: u16 },
+    /// Envelope received twice
+    Replayed{ sender: u16, msg_id: u64 },
+    /// Message of a party that does not take part in the run
+    UnknownSender(u16),
+    /// Message to an index outside the participants of the run
+    UnknownRecipient(u16),
+    /// The key file of the local party cannot be read
+    KeyFile{ path: String, reason: String },
+    /// The swarm driver is gone, the node is shutting down
+    Stopped,
+    /// The run waited too long, `parties` went silent first, after their messages of round `since`
+    Timeout{ timeout: Timeout, since: Option<u16>, parties: Vec<u16> },
+    /// Another participant gave up the run
+    Aborted{ party: u16, reason: String },
+    Channel(ChannelError),
+    Echo(EchoError),
+}
+
+impl fmt::Display for NetworkError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self{
+            NetworkError::Publish(e) => write!(f, "cannot publish broadcast: {}", e),
+            NetworkError::InsufficientPeers => write!(f, "cannot publish broadcast, no party is connected"),
+            NetworkError::Undelivered { party, reason } => write!(f, "message to party {} not delivered: {}", party, reason),
+            NetworkError::Serialization(e) => write!(f, "cannot serialize msg: {}", e),
+            NetworkError::Deserialization { sender, error } => write!(f, "cannot deserialize msg from party {}: {}", sender, error),
+            NetworkError::RoundMismatch { sender, round, actual } => write!(f, "party {} sent a round {} message for round {}", sender, actual, round),
+            NetworkError::UnsupportedVersion { sender, version } => write!(f, "party {} uses protocol version {}, expected {}", sender, version, PROTOCOL_VERSION),
+            NetworkError::WrongSession { sender } => write!(f, "message of party {} belongs to another session", sender),
+            NetworkError::Malformed { sender } => write!(f, "malformed envelope from party {}", sender),
+            NetworkError::Replayed { sender, msg_id } => write!(f, "message {} of party {} received twice", msg_id, sender),
+            NetworkError::UnknownSender(party) => write!(f, "message from party {}, not a participant", party),
+            NetworkError::UnknownRecipient(index) => write!(f, "message to participant {}, there is none", index),
+            NetworkError::KeyFile { path, reason } => write!(f, "cannot load key file {}: {}", path, reason),
+            NetworkError::Stopped => write!(f, "network is stopped"),
+            NetworkError::Timeout { timeout, since: Some(round), parties } => write!(f, "{} timeout, no message of parties {:?} after round {}", timeout, parties, round),
+            NetworkError::Timeout { timeout, since: None, parties } => write!(f, "{} timeout, no message of parties {:?}", timeout, parties),
+            NetworkError::Aborted { party, reason } => write!(f, "party {} aborted the run: {}", party, reason),
+            NetworkError::Channel(e) => write!(f, "P2P channel error: {}", e),
+            NetworkError::Echo(e) => write!(f, "broadcast error: {}", e),
+        }
+    }
+}
+
+impl Error for NetworkError{}
+
+impl From<PublishError> for NetworkError{
+    fn from(e: PublishError) -> Self {
+        match e{
+            PublishError::InsufficientPeers => NetworkError::InsufficientPeers,
+            e => NetworkError::Publish(e),
+        }
+    }
+}
+
+impl From<bincode::Error> for NetworkError{
+    fn from(e: bincode::Error) -> Self {
+        NetworkError::Serialization(e)
+    }
+}
+
+impl From<ChannelError> for NetworkError{
+    fn from(e: ChannelError) -> Self {
+        NetworkError::Channel(e)
+    }
+}
+
+impl From<EchoError> for NetworkError{
+    fn from(e: EchoError) -> Self {
+        NetworkError::Echo(e)
+    }
+}
@@ -0,0 +1,141 @@
+use std::{error::Error, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}};
+
+use libp2p::{gossipsub, PeerId};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::off_chain::network::{channel::ChannelKeys, deadline::SessionTimeouts, driver::{Command, ConnectedParties, DriverHandle, SwarmDriver}, envelope::{EnvelopeWriter, Payload}, registry::PeerRegistry, session::{Participants, ProtocolKind, SessionAlreadyOpen, SessionId, SessionRouter}, setup::{NetworkOptions, NetworkSetup}, sink::OutgoingSink, stream::IncomingStream};
+
+/// Long-lived party node, runs the swarm for the whole lifetime of the service.
+///
+/// The swarm is owned by a [`SwarmDriver`] task, so connections, gossipsub heartbeats and mDNS stay alive,
+/// peers that dropped are redialed, and every received protocol message is routed to the session it belongs to.
+/// Any number of sessions can run concurrently, see [`Node::session`]. Broadcasts go through gossipsub, P2P
+/// messages are sent directly to the recipient, encrypted to its key, and acknowledged, see [`super::direct`]
+/// and [`super::channel`]. A session that fails or waits past its [`SessionTimeouts`] tells the other
+/// participants it gave up, so they stop waiting for it.
+pub struct Node{
+    pub local_party_id: u16,
+    pub n: u16,
+    registry: Arc<PeerRegistry>,
+    router: Arc<Mutex<SessionRouter>>,
+    connected: Arc<ConnectedParties>,
+    commands: UnboundedSender<Command>,
+    channel_keys: Arc<ChannelKeys>,
+    timeouts: SessionTimeouts,
+}
+
+impl Node{
+    pub async fn start(local_party_id: u16, registry: Arc<PeerRegistry>, options: &NetworkOptions) -> Result<Arc<Node>, Box<dyn Error>>{
+        let network_setup = NetworkSetup::setup_swarm(local_party_id, &registry, options).await?;
+        Ok(Self::from_setup(network_setup, local_party_id, registry))
+    }
+
+    pub fn from_setup(network_setup: NetworkSetup, local_party_id: u16, registry: Arc<PeerRegistry>) -> Arc<Node>{
+        let router = Arc::new(Mutex::new(SessionRouter::default()));
+        let channel_keys = Arc::clone(&network_setup.channel_keys);
+        let timeouts = network_setup.timeouts;
+        let connected = Arc::new(ConnectedParties::default());
+        let commands = SwarmDriver::spawn(network_setup, Arc::clone(&registry), Arc::clone(&router), Arc::clone(&connected));
+
+        Arc::new(Node{
+            local_party_id,
+            n: registry.n(),
+            registry,
+            router,
+            connected,
+            commands,
+            channel_keys,
+            timeouts,
+        })
+    }
+
+    /// Parties among `parties`, other than the local one, the node has no connection to
+    pub fn unreachable(&self, parties: &[u16]) -> Vec<u16>{
+        parties.iter().copied().filter(|party| *party != self.local_party_id && !self.connected.contains(*party)).collect()
+    }
+
+    /// Opens a session for one protocol run, identified by the protocol and its execution id
+    pub fn session(&self, kind: ProtocolKind, exec_id: &[u8]) -> Result<Session<'_>, SessionAlreadyOpen>{
+        let id = SessionId::new(kind, exec_id);
+        self.router.lock().expect("Cannot lock router").open(id)?;
+        Ok(Session { node: self, id, writer: EnvelopeWriter::new(id, self.local_party_id), ended: Arc::new(AtomicBool::new(false)) })
+    }
+}
+
+/// Party that wrote a gossipsub message
+///
+/// `propagation_source` is only the neighbour that relayed the message, the author is the signed `source`,
+/// which gossipsub checks against the message signature in strict validation mode. Unsigned messages and
+/// messages of peers outside the registry are rejected.
+pub fn message_author(registry: &PeerRegistry, propagation_source: &PeerId, message: &gossipsub::Message) -> Option<u16>{
+    // Strict validation drops messages whose signature does not match their source
+    let Some(source) = message.source else {
+        println!("Ignoring unsigned message relayed by {}", propagation_source);
+        return None;
+    };
+    let author = registry.party_index(&source);
+    if author.is_none(){
+        println!("Ignoring message of {} relayed by {}, not a party", source, propagation_source);
+    }
+    author
+}
+
+/// One protocol run on the node, its messages are kept apart from every other session
+pub struct Session<'a>{
+    node: &'a Node,
+    id: SessionId,
+    writer: EnvelopeWriter,
+    /// Set once the run succeeded or was aborted, by this party or another one
+    ended: Arc<AtomicBool>,
+}
+
+impl Session<'_>{
+    /// Closes the session of a successful run, dropping it instead aborts the run for the other participants
+    pub fn finish(self){
+        self.ended.store(true, Ordering::Relaxed);
+    }
+
+    /// Delivery for a run between all the parties
+    pub fn delivery<T>(&self) -> (IncomingStream<T>, OutgoingSink<T>){
+        self.delivery_among(Participants::all(self.node.n))
+    }
+
+    /// Delivery for a run between some of the parties, they are numbered by their position in `participants`
+    pub fn delivery_among<T>(&self, participants: Participants) -> (IncomingStream<T>, OutgoingSink<T>){
+        // A new delivery takes over the messages of the session, a previous stream ends
+        let receiver = self.node.router.lock().expect("Cannot lock router").subscribe(&self.id);
+        let incoming = IncomingStream::new(
+            receiver,
+            self.node.commands.clone(),
+            self.writer.clone(),
+            Arc::clone(&self.node.channel_keys),
+            self.id,
+            self.node.local_party_id,
+            participants.clone(),
+            self.node.timeouts,
+            Arc::clone(&self.ended),
+        );
+        let outgoing = OutgoingSink::new(
+            self.node.commands.clone(),
+            self.writer.clone(),
+            Arc::clone(&self.node.registry),
+            Arc::clone(&self.node.channel_keys),
+            self.id,
+            participants,
+        );
+        (incoming, outgoing)
+    }
+}
+
+impl Drop for Session<'_>{
+    fn drop(&mut self) {
+        // The run failed outside the network, the other participants would wait for us until they time out
+        if !self.ended.swap(true, Ordering::Relaxed){
+            let abort = self.writer.write(0, None, Payload::Abort("protocol failed".to_string()));
+            if let Err(e) = abort.and_then(|data| DriverHandle::new(self.node.commands.clone()).broadcast(data)){
+                println!("[{}] Cannot abort the session: {}", self.id, e);
+            }
+        }
+        self.node.router.lock().expect("Cannot lock router").close(&self.id);
+    }
+}
@@ -0,0 +1,209 @@
+use std::{collections::HashMap, error::Error, fmt, fs, io, path::Path};
+
+use libp2p::{identity::PublicKey, Multiaddr, PeerId};
+use serde::Deserialize;
+
+/// Directory of the registry, `peers.json`, and of the party key files when their paths are not configured
+pub const DEFAULT_DATA_DIR: &str = "src/data";
+
+#[derive(Deserialize)]
+struct RegistryFile{
+    peers: Vec<PeerEntry>,
+}
+
+#[derive(Deserialize)]
+struct PeerEntry{
+    party_index: u16,
+    peer_id: String,
+    #[serde(default)]
+    addrs: Vec<String>,
+    /// Protobuf encoded libp2p public key, hex
+    #[serde(default)]
+    public_key: Option<String>,
+}
+
+/// One party of the network
+#[derive(Clone, Debug)]
+pub struct Peer{
+    pub party_index: u16,
+    pub peer_id: PeerId,
+    pub addrs: Vec<Multiaddr>,
+    pub public_key: Option<PublicKey>,
+}
+
+impl Peer{
+    /// Listed public key, or the one inlined in the peer id for ed25519 identities
+    pub fn identity_key(&self) -> Option<PublicKey>{
+        self.public_key.clone().or_else(|| PublicKey::try_decode_protobuf(self.peer_id.as_ref().digest()).ok())
+    }
+}
+
+#[derive(Debug)]
+pub enum RegistryError{
+    Io(io::Error),
+    Json(serde_json::Error),
+    InvalidPeerId{ party_index: u16, peer_id: String },
+    InvalidAddr{ party_index: u16, addr: String },
+    InvalidPublicKey(u16),
+    PublicKeyMismatch(u16),
+    DuplicateParty(u16),
+    DuplicatePeer(PeerId),
+    MissingParty(u16),
+    TooFewParties,
+}
+
+impl fmt::Display for RegistryError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self{
+            RegistryError::Io(e) => write!(f, "cannot read peer registry: {}", e),
+            RegistryError::Json(e) => write!(f, "invalid peer registry: {}", e),
+            RegistryError::InvalidPeerId { party_index, peer_id } => write!(f, "invalid peer id {} for party {}", peer_id, party_index),
+            RegistryError::InvalidAddr { party_index, addr } => write!(f, "invalid address {} for party {}", addr, party_index),
+            RegistryError::InvalidPublicKey(party_index) => write!(f, "invalid public key for party {}", party_index),
+            RegistryError::PublicKeyMismatch(party_index) => write!(f, "public key of party {} does not match its peer id", party_index),
+            RegistryError::DuplicateParty(party_index) => write!(f, "party {} is listed twice", party_index),
+            RegistryError::DuplicatePeer(peer_id) => write!(f, "peer {} is listed twice", peer_id),
+            RegistryError::MissingParty(party_index) => write!(f, "party {} is missing, parties must be numbered from 0", party_index),
+            RegistryError::TooFewParties => write!(f, "the registry must list at least 2 parties"),
+        }
+    }
+}
+
+impl Error for RegistryError{}
+
+impl From<io::Error> for RegistryError{
+    fn from(e: io::Error) -> Self {
+        RegistryError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for RegistryError{
+    fn from(e: serde_json::Error) -> Self {
+        RegistryError::Json(e)
+    }
+}
+
+/// Parties of the network and their libp2p identities, loaded at startup instead of compiled in
+pub struct PeerRegistry{
+    peers: Vec<Peer>,
+    party_by_peer: HashMap<PeerId, u16>,
+}
+
+impl PeerRegistry{
+    pub fn load(path: impl AsRef<Path>) -> Result<PeerRegistry, RegistryError>{
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<PeerRegistry, RegistryError>{
+        let file: RegistryFile = serde_json::from_str(json)?;
+
+        let mut peers: Vec<Peer> = file.peers.into_iter().map(Self::parse_entry).collect::<Result<_, _>>()?;
+        peers.sort_by_key(|peer| peer.party_index);
+
+        if peers.len() < 2{
+            return Err(RegistryError::TooFewParties);
+        }
+        for (expected, peer) in peers.iter().enumerate(){
+            match peer.party_index.cmp(&(expected as u16)){
+                std::cmp::Ordering::Less => return Err(RegistryError::DuplicateParty(peer.party_index)),
+                std::cmp::Ordering::Greater => return Err(RegistryError::MissingParty(expected as u16)),
+                std::cmp::Ordering::Equal => {}
+            }
+        }
+
+        let mut party_by_peer = HashMap::new();
+        for peer in &peers{
+            if party_by_peer.insert(peer.peer_id, peer.party_index).is_some(){
+                return Err(RegistryError::DuplicatePeer(peer.peer_id));
+            }
+        }
+
+        Ok(PeerRegistry { peers, party_by_peer })
+    }
+
+    fn parse_entry(entry: PeerEntry) -> Result<Peer, RegistryError>{
+        let party_index = entry.party_index;
+        let peer_id: PeerId = entry.peer_id.parse()
+            .map_err(|_| RegistryError::InvalidPeerId { party_index, peer_id: entry.peer_id.clone() })?;
+        let addrs = entry.addrs.into_iter()
+            .map(|addr| addr.parse().map_err(|_| RegistryError::InvalidAddr { party_index, addr }))
+            .collect::<Result<_, _>>()?;
+
+        let public_key = match entry.public_key{
+            Some(public_key) => {
+                let bytes = hex::decode(public_key).map_err(|_| RegistryError::InvalidPublicKey(party_index))?;
+                let public_key = PublicKey::try_decode_protobuf(&bytes).map_err(|_| RegistryError::InvalidPublicKey(party_index))?;
+                if public_key.to_peer_id() != peer_id{
+                    return Err(RegistryError::PublicKeyMismatch(party_index));
+                }
+                Some(public_key)
+            }
+            None => None,
+        };
+
+        Ok(Peer { party_index, peer_id, addrs, public_key })
+    }
+
+    pub fn n(&self) -> u16{
+        self.peers.len() as u16
+    }
+
+    pub fn party_index(&self, peer_id: &PeerId) -> Option<u16>{
+        self.party_by_peer.get(peer_id).copied()
+    }
+
+    pub fn peer(&self, party_index: u16) -> Option<&Peer>{
+        self.peers.get(usize::from(party_index))
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &Peer>{
+        self.peers.iter()
+    }
+}
+
+#[cfg(test)]
+mod peer_registry_tests {
+    use libp2p::identity::Keypair;
+
+    use super::*;
+
+    fn entry(party_index: u16, keypair: &Keypair) -> serde_json::Value{
+        serde_json::json!({
+            "party_index": party_index,
+            "peer_id": keypair.public().to_peer_id().to_string(),
+            "addrs": ["/ip4/127.0.0.1/tcp/4000"],
+            "public_key": hex::encode(keypair.public().encode_protobuf()),
+        })
+    }
+
+    #[test]
+    fn test_registry_maps_peers_and_parties() {
+        let keypairs: Vec<Keypair> = (0..4).map(|_| Keypair::generate_ed25519()).collect();
+        let peers: Vec<_> = keypairs.iter().enumerate().rev().map(|(i, keypair)| entry(i as u16, keypair)).collect();
+        let registry = PeerRegistry::from_json(&serde_json::json!({ "peers": peers }).to_string()).unwrap();
+
+        assert_eq!(registry.n(), 4);
+        let peer_id = keypairs[2].public().to_peer_id();
+        assert_eq!(registry.party_index(&peer_id), Some(2));
+        assert_eq!(registry.peer(2).unwrap().peer_id, peer_id);
+        assert_eq!(registry.peer(2).unwrap().addrs.len(), 1);
+        assert_eq!(registry.party_index(&Keypair::generate_ed25519().public().to_peer_id()), None);
+    }
+
+    #[test]
+    fn test_invalid_registries_are_rejected() {
+        let a = Keypair::generate_ed25519();
+        let b = Keypair::generate_ed25519();
+
+        let gap = serde_json::json!({ "peers": [entry(0, &a), entry(2, &b)] }).to_string();
+        assert!(matches!(PeerRegistry::from_json(&gap), Err(RegistryError::MissingParty(1))));
+
+        let duplicate = serde_json::json!({ "peers": [entry(0, &a), entry(0, &b)] }).to_string();
+        assert!(matches!(PeerRegistry::from_json(&duplicate), Err(RegistryError::DuplicateParty(0))));
+
+        let mut wrong_key = entry(1, &b);
+        wrong_key["public_key"] = serde_json::json!(hex::encode(a.public().encode_protobuf()));
+        let mismatch = serde_json::json!({ "peers": [entry(0, &a), wrong_key] }).to_string();
+        assert!(matches!(PeerRegistry::from_json(&mismatch), Err(RegistryError::PublicKeyMismatch(1))));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,224 @@
+use std::{collections::{HashMap, VecDeque}, error::Error, fmt, sync::Arc, time::{Duration, Instant}};
+
+use cggmp21::round_based::{MessageType, PartyIndex};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use super::envelope::Envelope;
+
+// Messages for a session nobody opened locally are kept this long, in case the local party joins late
+const UNCLAIMED_INBOX_TTL: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ProtocolKind{
+    Keygen,
+    AuxInfo,
+    Signing,
+    Presigning,
+    /// Single round exchanging partial signatures issued from presignatures
+    OnlineSigning,
+    /// Resharing of zero that rotates threshold key shares
+    KeyRefresh,
+}
+
+impl ProtocolKind{
+    /// Whether broadcasts are echoed by the network, see [`super::echo`]
+    ///
+    /// Keygen and aux info run without their own reliability round, the other protocols check broadcasts
+    /// themselves or have a single round.
+    pub fn echoes_broadcasts(&self) -> bool{
+        matches!(self, ProtocolKind::Keygen | ProtocolKind::AuxInfo)
+    }
+}
+
+/// Identifies one protocol run, all parties derive the same id from the shared execution id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SessionId{
+    pub kind: ProtocolKind,
+    pub eid: [u8; 32],
+}
+
+impl SessionId{
+    pub fn new(kind: ProtocolKind, exec_id: &[u8]) -> SessionId{
+        SessionId { kind, eid: Sha256::digest(exec_id).into() }
+    }
+}
+
+impl fmt::Display for SessionId{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}/{}", self.kind, hex::encode(&self.eid[..8]))
+    }
+}
+
+/// Parties taking part in a protocol run, by their keygen index
+///
+/// cggmp21 numbers the parties of a signing run 0..t in the order of the signer set, while the network
+/// addresses them by keygen index. The position of a party in the list is its index in the protocol.
+#[derive(Clone, Debug)]
+pub struct Participants(Arc<Vec<PartyIndex>>);
+
+impl Participants{
+    pub fn all(n: u16) -> Participants{
+        Participants(Arc::new((0..n).collect()))
+    }
+
+    pub fn new(parties: &[PartyIndex]) -> Participants{
+        Participants(Arc::new(parties.to_vec()))
+    }
+
+    /// Index in the protocol of the party with this keygen index, `None` if it does not take part
+    pub fn protocol_index(&self, party: PartyIndex) -> Option<PartyIndex>{
+        self.0.iter().position(|p| *p == party).map(|i| i as PartyIndex)
+    }
+
+    /// Keygen indexes of the parties, in protocol order
+    pub fn parties(&self) -> &[PartyIndex]{
+        &self.0
+    }
+
+    /// Keygen index of the party with this index in the protocol
+    pub fn party(&self, protocol_index: PartyIndex) -> Option<PartyIndex>{
+        self.0.get(usize::from(protocol_index)).copied()
+    }
+}
+
+/// Envelope received from its sender, on gossipsub or directly
+pub struct RoutedMessage{
+    pub msg_type: MessageType,
+    pub envelope: Envelope,
+}
+
+struct Inbox{
+    /// Messages kept until the session has a stream
+    queue: VecDeque<RoutedMessage>,
+    stream: Option<UnboundedSender<RoutedMessage>>,
+    claimed: bool,
+    created: Instant,
+}
+
+impl Inbox{
+    fn new() -> Inbox{
+        Inbox { queue: VecDeque::new(), stream: None, claimed: false, created: Instant::now() }
+    }
+}
+
+#[derive(Debug)]
+pub struct SessionAlreadyOpen(pub SessionId);
+
+impl fmt::Display for SessionAlreadyOpen{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "session {} is already running on this node", self.0)
+    }
+}
+
+impl Error for SessionAlreadyOpen{}
+
+/// Demultiplexes messages received on the shared topics into per-session inboxes
+#[derive(Default)]
+pub struct SessionRouter{
+    inboxes: HashMap<SessionId, Inbox>,
+}
+
+impl SessionRouter{
+    pub fn open(&mut self, session_id: SessionId) -> Result<(), SessionAlreadyOpen>{
+        let inbox = self.inboxes.entry(session_id).or_insert_with(Inbox::new);
+        if inbox.claimed{
+            return Err(SessionAlreadyOpen(session_id));
+        }
+        inbox.claimed = true;
+        Ok(())
+    }
+
+    pub fn close(&mut self, session_id: &SessionId){
+        self.inboxes.remove(session_id);
+    }
+
+    pub fn route(&mut self, msg: RoutedMessage){
+        self.inboxes.retain(|_, inbox| inbox.claimed || inbox.created.elapsed() < UNCLAIMED_INBOX_TTL);
+
+        let inbox = self.inboxes.entry(msg.envelope.session_id).or_insert_with(Inbox::new);
+        let msg = match &inbox.stream{
+            Some(stream) => match stream.send(msg){
+                Ok(()) => return,
+                // The stream was dropped, messages wait for the next one
+                Err(e) => e.0,
+            },
+            None => msg,
+        };
+        inbox.stream = None;
+        inbox.queue.push_back(msg);
+    }
+
+    /// Channel of the messages of an open session, starting with those received so far
+    ///
+    /// The channel of a closed session is already closed.
+    pub fn subscribe(&mut self, session_id: &SessionId) -> UnboundedReceiver<RoutedMessage>{
+        let (sender, receiver) = unbounded_channel();
+        if let Some(inbox) = self.inboxes.get_mut(session_id){
+            for msg in inbox.queue.drain(..){
+                let _ = sender.send(msg);
+            }
+            inbox.stream = Some(sender);
+        }
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod session_router_tests {
+    use tokio::sync::mpsc::error::TryRecvError;
+
+    use super::*;
+    use crate::off_chain::network::envelope::{Payload, PROTOCOL_VERSION};
+
+    fn routed(session_id: SessionId, msg_id: u64) -> RoutedMessage{
+        let envelope = Envelope { version: PROTOCOL_VERSION, session_id, round: 1, sender: 1, recipient: None, msg_id, payload: Payload::Broadcast(vec![]) };
+        RoutedMessage { msg_type: MessageType::Broadcast, envelope }
+    }
+
+    fn msg_id(msg: Result<RoutedMessage, TryRecvError>) -> Result<u64, TryRecvError>{
+        msg.map(|msg| msg.envelope.msg_id)
+    }
+
+    #[test]
+    fn test_messages_are_routed_per_session() {
+        let mut router = SessionRouter::default();
+        let keygen = SessionId::new(ProtocolKind::Keygen, b"exec");
+        let signing = SessionId::new(ProtocolKind::Signing, b"exec");
+
+        router.route(routed(signing, 1));
+        router.open(keygen).unwrap();
+        router.open(signing).unwrap();
+        router.route(routed(keygen, 2));
+
+        let mut keygen_stream = router.subscribe(&keygen);
+        let mut signing_stream = router.subscribe(&signing);
+        router.route(routed(keygen, 3));
+
+        assert_eq!(msg_id(keygen_stream.try_recv()), Ok(2));
+        assert_eq!(msg_id(keygen_stream.try_recv()), Ok(3));
+        assert!(keygen_stream.try_recv().is_err());
+        assert_eq!(msg_id(signing_stream.try_recv()), Ok(1));
+        assert!(router.open(keygen).is_err());
+
+        // Messages received while no stream is listening are not lost
+        drop(signing_stream);
+        router.route(routed(signing, 4));
+        assert_eq!(msg_id(router.subscribe(&signing).try_recv()), Ok(4));
+
+        router.close(&keygen);
+        assert!(matches!(keygen_stream.try_recv(), Err(TryRecvError::Disconnected)));
+        assert!(matches!(router.subscribe(&keygen).try_recv(), Err(TryRecvError::Disconnected)));
+    }
+
+    #[test]
+    fn test_participants_map_keygen_indexes() {
+        let participants = Participants::new(&[0, 2, 3]);
+
+        assert_eq!(participants.protocol_index(2), Some(1));
+        assert_eq!(participants.protocol_index(1), None);
+        assert_eq!(participants.party(2), Some(3));
+        assert_eq!(participants.party(3), None);
+    }
+}
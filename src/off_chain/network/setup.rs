@@ -1,24 +1,124 @@
-use std::{error::Error, fs::File, hash::{DefaultHasher, Hash, Hasher}, io::{self, Read}, time::Duration};
+use std::{collections::HashSet, error::Error, fs::File, path::{Path, PathBuf}, sync::Arc, hash::{DefaultHasher, Hash, Hasher}, io::{self, Read}, str::FromStr, time::{Duration, Instant}};
 
 use futures::StreamExt;
-use libp2p::{gossipsub::{self, IdentTopic}, identity, mdns, noise, swarm::SwarmEvent, tcp, yamux, Swarm, SwarmBuilder};
+use libp2p::{gossipsub::{self, IdentTopic}, identity, mdns, multiaddr::Protocol, noise, swarm::{behaviour::toggle::Toggle, SwarmEvent}, tcp, yamux, Multiaddr, Swarm, SwarmBuilder};
 
-use super::behaviour::{MyBehaviour, MyBehaviourEvent};
+use super::{behaviour::{MyBehaviour, MyBehaviourEvent}, channel::ChannelKeys, deadline::SessionTimeouts, dialer::Dialer, direct, error::NetworkError, registry::{PeerRegistry, DEFAULT_DATA_DIR}};
+
+// Party nodes are long-lived, keep connections open between protocol sessions
+const IDLE_CONNECTION_TIMEOUT_SECS: u64 = 600;
+const DEFAULT_STARTUP_TIMEOUT_SECS: u64 = 30;
+/// How often peers waiting for a redial are checked
+pub const DIAL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Transports the node listens and dials on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport{
+    /// TCP with noise and yamux
+    Tcp,
+    /// QUIC v1 over UDP, encrypted and multiplexed by QUIC itself
+    Quic,
+}
+
+impl Transport{
+    /// Transport an address is for, `None` for addresses neither transport can use
+    pub fn of(addr: &Multiaddr) -> Option<Transport>{
+        let mut transport = None;
+        for protocol in addr.iter(){
+            match protocol{
+                Protocol::Tcp(_) => transport = Some(Transport::Tcp),
+                Protocol::QuicV1 => transport = Some(Transport::Quic),
+                _ => {}
+            }
+        }
+        transport
+    }
+
+    /// Listen address on a random port, for nodes without a fixed address
+    fn any_addr(&self) -> Multiaddr{
+        match self{
+            Transport::Tcp => "/ip4/0.0.0.0/tcp/0".parse().expect("Valid multiaddr"),
+            Transport::Quic => "/ip4/0.0.0.0/udp/0/quic-v1".parse().expect("Valid multiaddr"),
+        }
+    }
+}
+
+impl FromStr for Transport{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str(){
+            "tcp" => Ok(Transport::Tcp),
+            "quic" => Ok(Transport::Quic),
+            other => Err(format!("unknown transport {}, expected tcp or quic", other)),
+        }
+    }
+}
+
+/// How the node connects to its peers
+#[derive(Clone, Debug)]
+pub struct NetworkOptions{
+    /// Discover peers on the local network, in addition to the static addresses of the registry
+    pub mdns: bool,
+    /// Only addresses of these transports are listened on and dialed
+    pub transports: Vec<Transport>,
+    /// Overrides the addresses of the local party in the registry
+    pub listen_addrs: Vec<Multiaddr>,
+    /// Applied to every session of the node
+    pub timeouts: SessionTimeouts,
+    /// How long the node waits for every party at startup, the missing ones are connected to later
+    pub startup_timeout: Duration,
+    /// Identity key of the local party, `party_{i}_key.json` in [`DEFAULT_DATA_DIR`] when not set
+    pub key_file: Option<PathBuf>,
+}
+
+impl Default for NetworkOptions{
+    fn default() -> Self {
+        NetworkOptions { mdns: true, transports: vec![Transport::Quic, Transport::Tcp], listen_addrs: vec![], timeouts: SessionTimeouts::default(), startup_timeout: Duration::from_secs(DEFAULT_STARTUP_TIMEOUT_SECS), key_file: None }
+    }
+}
+
+impl NetworkOptions{
+    pub fn allows(&self, addr: &Multiaddr) -> bool{
+        Transport::of(addr).is_some_and(|transport| self.transports.contains(&transport))
+    }
+}
 
 pub struct NetworkSetup{
     pub broadcast_topic: IdentTopic, 
-    pub my_topic: IdentTopic, 
     pub swarm: Swarm<MyBehaviour>, 
+    pub dialer: Dialer,
+    pub channel_keys: Arc<ChannelKeys>,
+    pub timeouts: SessionTimeouts,
 }
 impl NetworkSetup{
-    pub async fn setup_swarm(local_party_id: u16,n: u16) -> Result<NetworkSetup, Box<dyn Error>>{
+    /// Identity of the local party, its peer id must match the registry
+    fn load_keypair(path: &Path) -> Result<identity::Keypair, NetworkError>{
+        let key_file = |reason: String| NetworkError::KeyFile { path: path.display().to_string(), reason };
+
+        let mut json_str = String::new();
+        File::open(path).and_then(|mut file| file.read_to_string(&mut json_str)).map_err(|e| key_file(e.to_string()))?;
+        let keypair_bytes: Vec<u8> = serde_json::from_str(&json_str).map_err(|e| key_file(e.to_string()))?;
+        identity::Keypair::from_protobuf_encoding(&keypair_bytes).map_err(|e| key_file(e.to_string()))
+    }
+
+    pub async fn setup_swarm(local_party_id: u16, registry: &PeerRegistry, options: &NetworkOptions) -> Result<NetworkSetup, Box<dyn Error>>{
        
-        let mut file = File::open(format!("src/data/party_{}_key.json", local_party_id)).expect("Cannot open file");
-        let mut json_str = String::new(); 
-        file.read_to_string(&mut json_str).expect("Cannot read file"); 
-        let keypair_bytes: Vec<u8> = serde_json::from_str(&json_str).expect("Invalid json");
-        let keypair  = identity::Keypair::from_protobuf_encoding(&keypair_bytes).expect("Cannot conveert to keypair");  
+        let key_file = options.key_file.clone()
+            .unwrap_or_else(|| Path::new(DEFAULT_DATA_DIR).join(format!("party_{}_key.json", local_party_id)));
+        let keypair = Self::load_keypair(&key_file)?;
+
+        let local_peer = registry.peer(local_party_id).ok_or_else(|| format!("Party {} is not in the peer registry", local_party_id))?;
+        if local_peer.peer_id != keypair.public().to_peer_id(){
+            return Err(format!("Key file of party {} does not match its peer id in the registry", local_party_id).into());
+        }
+        let n = registry.n();
+        let channel_keys = Arc::new(ChannelKeys::new(&keypair, registry, local_party_id)?);
+        if options.transports.is_empty(){
+            return Err("At least one transport must be enabled".into());
+        }
         
+        // Both transports are always built, the enabled ones are picked by the addresses listened on and dialed
         let mut swarm = SwarmBuilder::with_existing_identity(keypair)
             .with_tokio()
             .with_tcp(
@@ -26,6 +126,7 @@ impl NetworkSetup{
                 noise::Config::new, 
                 yamux::Config::default
             )?
+            .with_quic()
             .with_behaviour(|key| {
         
                 let message_id_fn = |message: &gossipsub::Message| {
@@ -42,52 +143,96 @@ impl NetworkSetup{
                     .validation_mode(gossipsub::ValidationMode::Strict)
                     .message_id_fn(message_id_fn)
                     .build()
-                    .map_err(|msg| io::Error::new(io::ErrorKind::Other, msg))?;
+                    .map_err(io::Error::other)?;
 
                 let gossipsub:  gossipsub::Behaviour = gossipsub::Behaviour::new(
                     gossipsub::MessageAuthenticity::Signed(key.clone()),
                     gossipsub_config,
                 )?;
 
-                let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), key.public().to_peer_id())?; 
-                Ok(MyBehaviour {gossipsub, mdns})
+                let mdns = options.mdns
+                    .then(|| mdns::tokio::Behaviour::new(mdns::Config::default(), key.public().to_peer_id()))
+                    .transpose()?;
+                Ok(MyBehaviour {gossipsub, mdns: Toggle::from(mdns), direct: direct::behaviour()})
         })?
+        .with_swarm_config(|cfg| cfg.with_idle_connection_timeout(Duration::from_secs(IDLE_CONNECTION_TIMEOUT_SECS)))
         .build();
 
         let broadcast_topic = IdentTopic::new("cggmp21/broadcast");
-        let my_topic = IdentTopic::new(format!("cggmp21/party/{local_party_id}"));
         
-        swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+        // Peers dial the addresses listed for us in the registry, a random port is enough when they discover us
+        let listen_addrs: Vec<Multiaddr> = if !options.listen_addrs.is_empty(){
+            options.listen_addrs.clone()
+        }else if !local_peer.addrs.is_empty(){
+            local_peer.addrs.iter().filter(|addr| options.allows(addr)).cloned().collect()
+        }else{
+            options.transports.iter().map(Transport::any_addr).collect()
+        };
+        if listen_addrs.is_empty(){
+            return Err(format!("None of the addresses of party {} uses an enabled transport", local_party_id).into());
+        }
+        for addr in listen_addrs{
+            if !options.allows(&addr){
+                return Err(format!("Cannot listen on {}, its transport is not enabled", addr).into());
+            }
+            swarm.listen_on(addr)?;
+        }
     
         swarm.behaviour_mut().gossipsub.subscribe(&broadcast_topic)?;
-        swarm.behaviour_mut().gossipsub.subscribe(&my_topic)?;
 
-        let mut subscribed_peers = 0; 
+        let mut dialer = Dialer::new(registry, local_party_id, options, Instant::now());
+        let undialable = dialer.undialable(registry, local_party_id);
+        if !options.mdns && !undialable.is_empty(){
+            println!("mDNS is disabled and parties {:?} have no address, they must dial us", undialable);
+        }
+
+        let mut dial_tick = tokio::time::interval(DIAL_INTERVAL);
+        // Peers that reconnect subscribe again, each one is counted once
+        let mut subscribed_peers = HashSet::new();
+        // A t-of-n key is usable without every party, parties that are down must not keep the node from serving
+        let startup_timeout = tokio::time::sleep(options.startup_timeout);
+        tokio::pin!(startup_timeout);
         loop{
-            match swarm.select_next_some().await{
+            let event = tokio::select!{
+                event = swarm.select_next_some() => event,
+                _ = &mut startup_timeout => {
+                    let missing: Vec<u16> = (0..n)
+                        .filter(|party| *party != local_party_id)
+                        .filter(|party| registry.peer(*party).is_none_or(|peer| !subscribed_peers.contains(&peer.peer_id)))
+                        .collect();
+                    println!("Parties {:?} did not connect in time, starting without them", missing);
+                    break;
+                }
+                _ = dial_tick.tick() => {
+                    dialer.dial_due(&mut swarm, Instant::now());
+                    continue;
+                }
+            };
+            dialer.on_swarm_event(&event, Instant::now());
+            match event{
                 SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) =>{
-                    for (peer_id, addr) in peers{
+                    // Only parties of the registry are dialed, anything else on the local network is ignored
+                    for (peer_id, addr) in peers.into_iter().filter(|(peer_id, addr)| registry.party_index(peer_id).is_some() && options.allows(addr)){
                         swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
                         swarm.dial(addr.clone())?;
                         println!("Discovered peer: {} on address {}", peer_id, addr);
                     }
                 }
-                SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Subscribed { peer_id, topic })) => {
-                    if topic == broadcast_topic.hash(){
-                        println!("{} subscribed to {}", peer_id, topic);
-                        subscribed_peers+=1; 
-                    }
+                SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Subscribed { peer_id, topic }))
+                    if topic == broadcast_topic.hash() && registry.party_index(&peer_id).is_some() => {
+                    println!("{} subscribed to {}", peer_id, topic);
+                    subscribed_peers.insert(peer_id);
                 }
                 SwarmEvent::NewListenAddr { address , ..} => {
                     println!("Listening on {address}");
                 }
                 _ => {}
             }
-            if subscribed_peers == n-1{
+            if subscribed_peers.len() == usize::from(n-1){
                 break; 
             }
         }
 
-        Ok(NetworkSetup { broadcast_topic, my_topic, swarm})
+        Ok(NetworkSetup { broadcast_topic, swarm, dialer, channel_keys, timeouts: options.timeouts })
     }
 }
\ No newline at end of file
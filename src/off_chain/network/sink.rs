@@ -1,23 +1,30 @@
-use core::panic;
-use std::{marker::PhantomData, sync::{Arc, Mutex}, task::Poll};
+use std::{marker::PhantomData, sync::Arc};
 
-use cggmp21::{round_based::{MessageDestination, Outgoing}, KeygenError};
+use cggmp21::round_based::{MessageDestination, Outgoing, ProtocolMessage};
 use futures::Sink;
-use libp2p::{gossipsub::IdentTopic, Swarm};
+use tokio::sync::mpsc::UnboundedSender;
 
-use crate::off_chain::network::behaviour::MyBehaviour;
+use crate::off_chain::network::{channel::ChannelKeys, driver::{Command, DriverHandle}, envelope::{EnvelopeWriter, Payload}, error::NetworkError, registry::PeerRegistry, session::{Participants, SessionId}};
 
 pub struct OutgoingSink<T>{
-    swarm: Arc<Mutex<Swarm<MyBehaviour>>>, 
-    broadcast_topic: IdentTopic, 
+    driver: DriverHandle,
+    writer: EnvelopeWriter,
+    registry: Arc<PeerRegistry>,
+    channel_keys: Arc<ChannelKeys>,
+    session_id: SessionId, 
+    participants: Participants,
     _phantom: PhantomData<T>,
 }
 
 impl<T> OutgoingSink<T>{
-    pub fn new(swarm: Arc<Mutex<Swarm<MyBehaviour>>>, broadcast_topic: IdentTopic) -> OutgoingSink<T>{
+    pub fn new(commands: UnboundedSender<Command>, writer: EnvelopeWriter, registry: Arc<PeerRegistry>, channel_keys: Arc<ChannelKeys>, session_id: SessionId, participants: Participants) -> OutgoingSink<T>{
         OutgoingSink{
-            swarm,  
-            broadcast_topic, 
+            driver: DriverHandle::new(commands),
+            writer,
+            registry,
+            channel_keys,
+            session_id, 
+            participants,
             _phantom: PhantomData
         }
     }
@@ -25,49 +32,44 @@ impl<T> OutgoingSink<T>{
 
 impl<T> Sink<Outgoing<T>> for OutgoingSink<T>
 where 
-    T: serde::Serialize + Unpin
+    T: serde::Serialize + ProtocolMessage + Unpin
 {
-    type Error = KeygenError;
+    type Error = NetworkError;
     
     fn poll_ready(self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+        std::task::Poll::Ready(Ok(()))
     }
     
     fn start_send(self: std::pin::Pin<&mut Self>, item: Outgoing<T>) -> Result<(), Self::Error> {
-        let serialized_msg = bincode::serialize(&item.msg).expect("Cannot serialize msg");
-        
         let this = self.get_mut();
 
-        let mut swarm_lock =  this.swarm.lock().expect("Cannot lock swarm");
+        let payload = bincode::serialize(&item.msg)?;
+        let round = item.msg.round();
 
-        if item.is_broadcast(){
-            let broadcast_topic = this.broadcast_topic.clone();
-            swarm_lock.behaviour_mut().gossipsub.publish(broadcast_topic, serialized_msg).expect("Cannot publish");
-            println!("Publishing to broadcast"); 
-        }else{
-            match item.recipient{
-                MessageDestination::OneParty(party_index) => {
-                    let party_topic = IdentTopic::new(format!("cggmp21/party/{party_index}"));    
-                    swarm_lock.behaviour_mut().gossipsub.publish(party_topic, serialized_msg).expect("Cannot publish");
-                    println!("Sending to party {}", party_index);
-                }, 
-                MessageDestination::AllParties => {
-                    drop(swarm_lock);
-                    panic!("invalid message");
-                  
-                }
+        match item.recipient{
+            MessageDestination::AllParties => {
+                this.driver.broadcast(this.writer.write(round, None, Payload::Broadcast(payload))?)?;
+                println!("[{}] Publishing to broadcast", this.session_id); 
+            }
+            MessageDestination::OneParty(protocol_index) => {
+                let party_index = this.participants.party(protocol_index).ok_or(NetworkError::UnknownRecipient(protocol_index))?;
+                let peer = this.registry.peer(party_index).ok_or(NetworkError::UnknownRecipient(protocol_index))?;
+                // Only the recipient can read P2P messages, whoever relays them
+                let sealed = this.channel_keys.seal(&this.session_id, round, party_index, &payload)?;
+                this.driver.direct(party_index, peer.peer_id, this.writer.write(round, Some(party_index), Payload::Sealed(sealed))?)?;
+                println!("[{}] Sending to party {}", this.session_id, party_index);
             }
         }
-        
-        drop(swarm_lock);
+
         Ok(())
     }
     
-    fn poll_flush(self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    /// Waits until the broadcasts are published and the P2P messages acknowledged
+    fn poll_flush(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        self.get_mut().driver.poll_sent(cx)
     }
     
-    fn poll_close(self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    fn poll_close(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        self.get_mut().driver.poll_sent(cx)
     }
-}
\ No newline at end of file
+}
@@ -1,19 +1,73 @@
-use std::{marker::PhantomData, sync::{Arc, Mutex}, task::Poll};
-use cggmp21::{round_based::{Incoming, MessageType}, signing::msg::Msg, supported_curves::Secp256k1, KeygenError};
-use futures::{Stream, StreamExt};
-use libp2p::{ gossipsub::{self, IdentTopic}, swarm::SwarmEvent, Swarm};
+use std::{marker::PhantomData, sync::{atomic::{AtomicBool, Ordering}, Arc}, task::{Context, Poll}};
+use cggmp21::{round_based::{Incoming, MessageType, ProtocolMessage}, signing::msg::Msg, supported_curves::Secp256k1};
+use futures::Stream;
 use sha2::Sha256;
-use crate::off_chain::network::{behaviour::{MyBehaviour, MyBehaviourEvent}, hash_map::PEER_TO_PARTY_MAP};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use crate::off_chain::network::{channel::{ChannelError, ChannelKeys}, deadline::{Deadline, SessionTimeouts}, driver::{Command, DriverHandle}, echo::EchoBroadcast, envelope::{EnvelopeCheck, EnvelopeWriter, Payload}, error::NetworkError, session::{Participants, RoutedMessage, SessionId}};
 
 pub struct IncomingStream<T>{
-    swarm: Arc<Mutex<Swarm<MyBehaviour>>>, 
-    my_topic: IdentTopic, 
-    broadcast_topic: IdentTopic, 
+    incoming: UnboundedReceiver<RoutedMessage>,
+    driver: DriverHandle,
+    writer: EnvelopeWriter,
+    channel_keys: Arc<ChannelKeys>,
+    session_id: SessionId, 
+    participants: Participants,
+    check: EnvelopeCheck,
+    /// Only for the protocols that rely on the network for reliable broadcast, see [`super::session::ProtocolKind::echoes_broadcasts`]
+    echo: Option<EchoBroadcast>,
+    deadline: Deadline,
+    /// Shared with the session, see [`super::node::Session::finish`]
+    ended: Arc<AtomicBool>,
     _phantom: PhantomData<T>
 }
 impl<T> IncomingStream<T>{
-    pub fn new(swarm: Arc<Mutex<Swarm<MyBehaviour>>>, my_topic: IdentTopic, broadcast_topic: IdentTopic) -> IncomingStream<T>{
-        IncomingStream { swarm, my_topic, broadcast_topic, _phantom: PhantomData}   
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(incoming: UnboundedReceiver<RoutedMessage>, commands: UnboundedSender<Command>, writer: EnvelopeWriter, channel_keys: Arc<ChannelKeys>, session_id: SessionId, local_party_id: u16, participants: Participants, timeouts: SessionTimeouts, ended: Arc<AtomicBool>) -> IncomingStream<T>{
+        IncomingStream{
+            incoming,
+            driver: DriverHandle::new(commands),
+            writer,
+            channel_keys,
+            session_id,
+            check: EnvelopeCheck::new(session_id, local_party_id),
+            echo: session_id.kind.echoes_broadcasts().then(|| EchoBroadcast::new(session_id, local_party_id, participants.clone())),
+            deadline: Deadline::new(timeouts, local_party_id, &participants),
+            ended,
+            participants,
+            _phantom: PhantomData
+        }
+    }
+
+    /// Tells the other participants which message we received, see [`EchoBroadcast`]
+    fn publish_echo(&mut self, sender: u16, round: u16, digest: [u8; 32]) -> Result<(), NetworkError>{
+        let envelope = self.writer.write(round, None, Payload::Echo { sender, digest })?;
+        self.driver.broadcast(envelope)
+    }
+
+    /// Tells the other participants the run is given up, unless it already ended
+    fn abort(&mut self, error: &NetworkError){
+        match error{
+            NetworkError::Aborted { .. } => self.ended.store(true, Ordering::Relaxed),
+            NetworkError::Stopped => {}
+            error => if !self.ended.swap(true, Ordering::Relaxed){
+                println!("[{}] Aborting session: {}", self.session_id, error);
+                let abort = self.writer.write(0, None, Payload::Abort(error.to_string()));
+                if let Err(e) = abort.and_then(|data| self.driver.broadcast(data)){
+                    println!("[{}] Cannot abort the session: {}", self.session_id, e);
+                }
+            }
+        }
+    }
+
+    /// Protocol message of an envelope for `round`
+    fn decode(sender: u16, round: u16, payload: &[u8]) -> Result<T, NetworkError>
+    where T: serde::de::DeserializeOwned + ProtocolMessage
+    {
+        let msg: T = bincode::deserialize(payload).map_err(|error| NetworkError::Deserialization { sender, error })?;
+        if msg.round() != round{
+            return Err(NetworkError::RoundMismatch { sender, round, actual: msg.round() });
+        }
+        Ok(msg)
     }
 }
 
@@ -30,94 +84,108 @@ fn extract_round(msg: &Msg<Secp256k1, Sha256>) -> usize{
 }
 
 impl<T> Stream for IncomingStream<T>
-where T: serde::de::DeserializeOwned + Unpin
+where T: serde::de::DeserializeOwned + ProtocolMessage + Unpin
 {
-    type Item = Result<Incoming<T>, KeygenError>; // <- generalize error 
+    type Item = Result<Incoming<T>, NetworkError>;
 
     fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<Self::Item>> {
        
         let this = self.get_mut();
 
-        let mut swarm = match this.swarm.lock() {
-            Ok(swarm) => swarm,
-            Err(_) => {
-                println!("Cannot lock"); 
-                return Poll::Pending
-            }
-        , 
+        let result = match this.poll_message(cx){
+            // Parties that died or hang would keep the protocol waiting forever
+            Poll::Pending => match this.deadline.poll_expired(cx){
+                Poll::Ready(e) => Poll::Ready(Some(Err(e))),
+                Poll::Pending => return Poll::Pending,
+            },
+            ready => ready,
         };
-        
-        match swarm.poll_next_unpin(cx){
-          
-            Poll::Ready(Some(event)) => {
-                if let SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Message { propagation_source, message_id, message })) = event{
-                    match bincode::deserialize::<T>(&message.data) {
-                        Ok(msg) => {
-
-                            let msg_type = if message.topic == this.broadcast_topic.clone().into(){
-                                MessageType::Broadcast
-                            }else if message.topic == this.my_topic.clone().into(){
-                                MessageType::P2P
-                            }else{
-                                println!("Wrong message type");
-                                drop(swarm);
-                                return Poll::Pending;
-                            }; 
-                            
-                            let sender = match PEER_TO_PARTY_MAP.get(&propagation_source.to_string()) {
-                                Some(&party_id) => party_id,
-                                None => {
-                                    println!("No party id found");
-                                    drop(swarm);
-                                    return Poll::Pending;
-                                }
-                            };
+        match &result{
+            Poll::Ready(Some(Ok(_))) => this.deadline.delivered(),
+            Poll::Ready(Some(Err(e))) => this.abort(e),
+            _ => {}
+        }
+        result
+    }
+}
 
+impl<T> IncomingStream<T>
+where T: serde::de::DeserializeOwned + ProtocolMessage
+{
+    fn poll_message(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Incoming<T>, NetworkError>>>{
+        loop{
+            // Echoes the other parties do not get would stall them
+            if let Poll::Ready(Err(e)) = self.driver.poll_sent(cx){
+                return Poll::Ready(Some(Err(e)));
+            }
 
-                            let bytes = message_id.0; 
-                            if bytes.len() > 8 {
-                                println!("Message id too long");
-                                drop(swarm);
-                                return Poll::Pending;
-                            }
+            // Echoed broadcasts reach the protocol only once every other participant echoed the same message
+            if let Some(delivery) = self.echo.as_mut().and_then(EchoBroadcast::ready){
+                let sender = self.participants.protocol_index(delivery.sender).expect("Echoed sender is a participant");
+                let result = Self::decode(delivery.sender, delivery.round, &delivery.payload).map(|msg| {
+                    println!("[{}] Received message from {}, message type {:?}, message_id :{}", self.session_id, sender, MessageType::Broadcast, delivery.id);
+                    Incoming{ id: delivery.id, sender, msg_type: MessageType::Broadcast, msg }
+                });
+                return Poll::Ready(Some(result));
+            }
 
-                            let mut byte_slice = [0u8; 8]; 
-                            for (i, &byte) in bytes.iter().enumerate(){
-                                byte_slice[i] = byte; 
-                            }
-                            let id = u64::from_be_bytes(byte_slice);
+            let RoutedMessage { msg_type, envelope } = match self.incoming.poll_recv(cx){
+                Poll::Ready(Some(routed)) => routed,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
 
-                            let incoming = Incoming{
-                                id, 
-                                sender, 
-                                msg_type, 
-                                msg 
-                            };
+            // Senders are numbered as in the protocol, a message of a party outside the run aborts it
+            let Some(sender) = self.participants.protocol_index(envelope.sender) else {
+                return Poll::Ready(Some(Err(NetworkError::UnknownSender(envelope.sender))));
+            };
+            if let Err(e) = self.check.check(msg_type, &envelope){
+                return Poll::Ready(Some(Err(e)));
+            }
+            self.deadline.heard(envelope.sender, envelope.round);
 
-                            println!("Received message from {sender}, message type {msg_type:?}, message_id :{}", id);
-                            drop(swarm);
-                            Poll::Ready(Some(Ok(incoming)))
-                           
-                        },   
-                        Err(_) => {
-                            println!("Cannot deserialize msg");
-                            drop(swarm);
-                            return Poll::Pending;
-                        }
+            let result = match envelope.payload{
+                Payload::Broadcast(payload) => match self.echo.as_mut(){
+                    // A party equivocating aborts the protocol
+                    Some(echo) => match echo.message(envelope.msg_id, envelope.sender, envelope.round, payload){
+                        Ok(Some(digest)) => self.publish_echo(envelope.sender, envelope.round, digest),
+                        Ok(None) => Ok(()),
+                        Err(e) => Err(e.into()),
+                    },
+                    // The protocol checks the consistency of broadcasts itself
+                    None => {
+                        let incoming = Self::decode(envelope.sender, envelope.round, &payload).map(|msg| {
+                            println!("[{}] Received message from {}, message type {:?}, message_id :{}", self.session_id, sender, msg_type, envelope.msg_id);
+                            Incoming{ id: envelope.msg_id, sender, msg_type, msg }
+                        });
+                        return Poll::Ready(Some(incoming));
                     }
-                }else{
-                    drop(swarm);
-                    return Poll::Pending;
+                },
+                Payload::Echo { sender: echoed, digest } => match self.echo.as_mut(){
+                    Some(echo) => echo.echo(envelope.sender, echoed, envelope.round, digest).map_err(NetworkError::from),
+                    // Rejected by the envelope check already
+                    None => Err(NetworkError::Malformed { sender: envelope.sender }),
+                },
+                Payload::Abort(reason) => Err(NetworkError::Aborted { party: envelope.sender, reason }),
+                // P2P messages are encrypted to the local party, failing to open one aborts the protocol
+                Payload::Sealed(sealed) => {
+                    let incoming = self.channel_keys.open(&self.session_id, envelope.sender, &sealed)
+                        .and_then(|(sealed_round, payload)| match sealed_round{
+                            round if round == envelope.round => Ok(payload),
+                            round => Err(ChannelError::RoundMismatch { sender: envelope.sender, sealed: round, actual: envelope.round }),
+                        })
+                        .map_err(NetworkError::from)
+                        .and_then(|payload| Self::decode(envelope.sender, envelope.round, &payload))
+                        .map(|msg| {
+                            println!("[{}] Received message from {}, message type {:?}, message_id :{}", self.session_id, sender, msg_type, envelope.msg_id);
+                            Incoming{ id: envelope.msg_id, sender, msg_type, msg }
+                        });
+                    return Poll::Ready(Some(incoming));
                 }
-            }, 
-            Poll::Ready(None) => {
-                drop(swarm);
-                return Poll::Ready(None);
-            },
-            Poll::Pending => {
-                drop(swarm);
-                return Poll::Pending;
-            },
+            };
+            if let Err(e) = result{
+                return Poll::Ready(Some(Err(e)));
+            }
         }
     }
 }
\ No newline at end of file
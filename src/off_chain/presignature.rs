@@ -0,0 +1,178 @@
+use std::{collections::{HashMap, HashSet}, error::Error, fmt, sync::Mutex, time::{Duration, Instant}};
+
+use cggmp21::{signing::Presignature, supported_curves::Secp256k1};
+
+struct PoolEntry{
+    key_id: String,
+    signers: Vec<u16>,
+    presignature: Presignature<Secp256k1>,
+    expires_at: Instant,
+}
+
+#[derive(Debug)]
+pub enum PresignatureError{
+    NotFound(String),
+    AlreadyUsed(String),
+    Expired(String),
+    WrongKey{ id: String, key_id: String },
+}
+
+impl fmt::Display for PresignatureError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self{
+            PresignatureError::NotFound(id) => write!(f, "presignature {} not found", id),
+            PresignatureError::AlreadyUsed(id) => write!(f, "presignature {} was already used", id),
+            PresignatureError::Expired(id) => write!(f, "presignature {} expired", id),
+            PresignatureError::WrongKey { id, key_id } => write!(f, "presignature {} was not generated for key {}", id, key_id),
+        }
+    }
+}
+
+impl Error for PresignatureError{}
+
+#[derive(Default)]
+struct PoolState{
+    entries: HashMap<String, PoolEntry>,
+    // Ids handed out or expired, a presignature id can never be used twice
+    used: HashSet<String>,
+}
+
+impl PoolState{
+    fn prune(&mut self, now: Instant){
+        let expired: Vec<String> = self.entries.iter()
+            .filter(|(_, entry)| entry.expires_at <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired{
+            self.entries.remove(&id);
+            self.used.insert(id);
+        }
+    }
+}
+
+/// Presignatures generated ahead of time, each one is consumed by exactly one signature.
+///
+/// All signers must consume the presignature generated in the same protocol run, so entries are
+/// identified by an id every party derives from the presigning execution id.
+pub struct PresignaturePool{
+    ttl: Duration,
+    state: Mutex<PoolState>,
+}
+
+impl PresignaturePool{
+    pub fn new(ttl: Duration) -> PresignaturePool{
+        PresignaturePool { ttl, state: Mutex::new(PoolState::default()) }
+    }
+
+    pub fn insert(&self, id: String, key_id: &str, signers: Vec<u16>, presignature: Presignature<Secp256k1>) -> Result<(), PresignatureError>{
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("Cannot lock presignature pool");
+        state.prune(now);
+
+        if state.used.contains(&id) || state.entries.contains_key(&id){
+            return Err(PresignatureError::AlreadyUsed(id));
+        }
+        state.entries.insert(id, PoolEntry { key_id: key_id.to_string(), signers, presignature, expires_at: now + self.ttl });
+        Ok(())
+    }
+
+    /// Signers of a presignature still in the pool
+    pub fn signers(&self, id: &str) -> Option<Vec<u16>>{
+        self.state.lock().expect("Cannot lock presignature pool").entries.get(id).map(|entry| entry.signers.clone())
+    }
+
+    /// Removes the presignature from the pool, it cannot be taken again even if signing fails
+    pub fn take(&self, id: &str, key_id: &str) -> Result<(Presignature<Secp256k1>, Vec<u16>), PresignatureError>{
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("Cannot lock presignature pool");
+
+        match state.entries.get(id){
+            Some(entry) if entry.key_id != key_id => {
+                return Err(PresignatureError::WrongKey { id: id.to_string(), key_id: key_id.to_string() });
+            }
+            Some(entry) if entry.expires_at <= now => {
+                state.prune(now);
+                return Err(PresignatureError::Expired(id.to_string()));
+            }
+            Some(_) => {}
+            None if state.used.contains(id) => return Err(PresignatureError::AlreadyUsed(id.to_string())),
+            None => return Err(PresignatureError::NotFound(id.to_string())),
+        }
+
+        let entry = state.entries.remove(id).expect("Entry was just found");
+        state.used.insert(id.to_string());
+        Ok((entry.presignature, entry.signers))
+    }
+
+    /// Drops every presignature of the key, they were issued from shares that are no longer current
+    pub fn discard(&self, key_id: &str){
+        let mut state = self.state.lock().expect("Cannot lock presignature pool");
+        let discarded: Vec<String> = state.entries.iter()
+            .filter(|(_, entry)| entry.key_id == key_id)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in discarded{
+            state.entries.remove(&id);
+            state.used.insert(id);
+        }
+    }
+
+    /// Ids of the presignatures ready for the key, with the time left before they expire
+    pub fn available(&self, key_id: &str) -> Vec<(String, Duration)>{
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("Cannot lock presignature pool");
+        state.prune(now);
+
+        let mut available: Vec<(String, Duration)> = state.entries.iter()
+            .filter(|(_, entry)| entry.key_id == key_id)
+            .map(|(id, entry)| (id.clone(), entry.expires_at - now))
+            .collect();
+        available.sort();
+        available
+    }
+}
+
+#[cfg(test)]
+mod presignature_pool_tests {
+    use cggmp21::generic_ec::{NonZero, Point, SecretScalar};
+    use rand_core::OsRng;
+
+    use super::*;
+
+    fn presignature() -> Presignature<Secp256k1>{
+        Presignature {
+            R: NonZero::<Point<Secp256k1>>::from_point(Point::generator().into()).unwrap(),
+            k: SecretScalar::random(&mut OsRng),
+            chi: SecretScalar::random(&mut OsRng),
+        }
+    }
+
+    #[test]
+    fn test_presignature_is_used_once() {
+        let pool = PresignaturePool::new(Duration::from_secs(60));
+        pool.insert("exec/0".to_string(), "key", vec![0, 1], presignature()).unwrap();
+        pool.insert("exec/1".to_string(), "key", vec![0, 1], presignature()).unwrap();
+        assert_eq!(pool.available("key").len(), 2);
+
+        assert!(matches!(pool.take("exec/0", "other key"), Err(PresignatureError::WrongKey { .. })));
+        assert_eq!(pool.signers("exec/0"), Some(vec![0, 1]));
+        let (_, signers) = pool.take("exec/0", "key").unwrap();
+        assert_eq!(signers, vec![0, 1]);
+
+        assert!(matches!(pool.take("exec/0", "key"), Err(PresignatureError::AlreadyUsed(_))));
+        assert_eq!(pool.signers("exec/0"), None);
+        assert!(matches!(pool.insert("exec/0".to_string(), "key", vec![0, 1], presignature()), Err(PresignatureError::AlreadyUsed(_))));
+        assert!(matches!(pool.take("exec/2", "key"), Err(PresignatureError::NotFound(_))));
+        assert_eq!(pool.available("key").len(), 1);
+    }
+
+    #[test]
+    fn test_expired_presignature_is_discarded() {
+        let pool = PresignaturePool::new(Duration::ZERO);
+        pool.insert("exec/0".to_string(), "key", vec![0, 1], presignature()).unwrap();
+
+        assert!(matches!(pool.take("exec/0", "key"), Err(PresignatureError::Expired(_))));
+        assert!(matches!(pool.take("exec/0", "key"), Err(PresignatureError::AlreadyUsed(_))));
+        assert!(pool.available("key").is_empty());
+    }
+}
\ No newline at end of file
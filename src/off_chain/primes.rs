@@ -0,0 +1,167 @@
+use std::{collections::VecDeque, sync::{Arc, Condvar, Mutex}, thread};
+
+use cggmp21::{security_level::SecurityLevel128, PregeneratedPrimes};
+use rand::RngCore;
+use rand_core::OsRng;
+use tokio::sync::Notify;
+
+use super::vault::KeyVault;
+
+#[derive(Default)]
+struct PoolState{
+    ready: VecDeque<(String, PregeneratedPrimes<SecurityLevel128>)>,
+    in_progress: usize,
+    // Takers waiting on an empty pool, the workers keep generating for them even past the target
+    waiting: usize,
+}
+
+struct Shared{
+    state: Mutex<PoolState>,
+    refill: Condvar,
+    ready: Notify,
+    target: usize,
+    vault: Option<Arc<KeyVault>>,
+}
+
+/// Caller of [`PrimePool::take`], no longer counted as waiting if it gives up before getting primes
+struct Taker<'a>{
+    shared: &'a Shared,
+    waiting: bool,
+}
+
+impl Drop for Taker<'_>{
+    fn drop(&mut self) {
+        if self.waiting{
+            self.shared.state.lock().expect("Cannot lock prime pool").waiting -= 1;
+        }
+    }
+}
+
+/// Paillier safe primes generated ahead of time for aux info generation and key refresh.
+///
+/// Prime search burns seconds to minutes of CPU, so it runs on dedicated worker threads rather than on
+/// the tokio runtime. The pool is refilled up to `target` sets as soon as one is taken. With a vault,
+/// ready primes are also kept on disk and survive restarts.
+pub struct PrimePool{
+    shared: Arc<Shared>,
+}
+
+impl PrimePool{
+    pub fn start(target: usize, workers: usize, vault: Option<Arc<KeyVault>>) -> PrimePool{
+        let mut state = PoolState::default();
+        if let Some(vault) = &vault{
+            match vault.primes_ids(){
+                Ok(ids) => {
+                    for id in ids{
+                        match vault.load_primes(&id){
+                            Ok(primes) => state.ready.push_back((id, primes)),
+                            Err(e) => println!("Cannot load primes {}: {}", id, e),
+                        }
+                    }
+                }
+                Err(e) => println!("Cannot list stored primes: {}", e),
+            }
+            println!("{} sets of primes restored from disk", state.ready.len());
+        }
+
+        let shared = Arc::new(Shared { state: Mutex::new(state), refill: Condvar::new(), ready: Notify::new(), target, vault });
+        for i in 0..workers.max(1){
+            let shared = Arc::clone(&shared);
+            thread::Builder::new()
+                .name(format!("prime-worker-{}", i))
+                .spawn(move || Self::work(shared))
+                .expect("Cannot spawn prime worker");
+        }
+
+        PrimePool { shared }
+    }
+
+    /// Takes a set of primes, waiting for a worker if the pool is empty
+    pub async fn take(&self) -> PregeneratedPrimes<SecurityLevel128>{
+        let mut taker = Taker { shared: &self.shared, waiting: false };
+        loop{
+            let taken = {
+                let mut state = self.shared.state.lock().expect("Cannot lock prime pool");
+                let taken = state.ready.pop_front();
+                match (&taken, taker.waiting){
+                    (Some(_), true) => {
+                        state.waiting -= 1;
+                        taker.waiting = false;
+                    }
+                    (None, false) => {
+                        state.waiting += 1;
+                        taker.waiting = true;
+                    }
+                    _ => {}
+                }
+                taken
+            };
+            self.shared.refill.notify_all();
+
+            match taken{
+                Some((id, primes)) => {
+                    if let Some(vault) = &self.shared.vault
+                        && let Err(e) = vault.remove_primes(&id){
+                        println!("Cannot remove used primes {}: {}", id, e);
+                    }
+                    return primes;
+                }
+                None => self.shared.ready.notified().await,
+            }
+        }
+    }
+
+    pub fn available(&self) -> usize{
+        self.shared.state.lock().expect("Cannot lock prime pool").ready.len()
+    }
+
+    fn work(shared: Arc<Shared>){
+        loop{
+            {
+                let mut state = shared.state.lock().expect("Cannot lock prime pool");
+                while state.ready.len() + state.in_progress >= shared.target + state.waiting{
+                    state = shared.refill.wait(state).expect("Cannot lock prime pool");
+                }
+                state.in_progress += 1;
+            }
+
+            let primes: PregeneratedPrimes<SecurityLevel128> = PregeneratedPrimes::generate(&mut OsRng);
+
+            let mut id = [0u8; 16];
+            OsRng.fill_bytes(&mut id);
+            let id = hex::encode(id);
+            if let Some(vault) = &shared.vault
+                && let Err(e) = vault.store_primes(&id, &primes){
+                println!("Cannot store primes {}: {}", id, e);
+            }
+
+            {
+                let mut state = shared.state.lock().expect("Cannot lock prime pool");
+                state.in_progress -= 1;
+                state.ready.push_back((id, primes));
+                println!("Primes ready, {} in the pool", state.ready.len());
+            }
+            shared.ready.notify_one();
+        }
+    }
+}
+#[cfg(test)]
+mod prime_pool_tests {
+    use futures::FutureExt;
+
+    use super::*;
+
+    #[test]
+    fn test_cancelled_take_stops_waiting() {
+        // No worker, the pool stays empty
+        let shared = Shared { state: Mutex::new(PoolState::default()), refill: Condvar::new(), ready: Notify::new(), target: 0, vault: None };
+        let pool = PrimePool { shared: Arc::new(shared) };
+
+        let mut take = Box::pin(pool.take());
+        assert!((&mut take).now_or_never().is_none());
+        assert_eq!(pool.shared.state.lock().unwrap().waiting, 1);
+
+        drop(take);
+        assert_eq!(pool.shared.state.lock().unwrap().waiting, 0);
+    }
+}
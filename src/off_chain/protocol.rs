@@ -1,6 +1,6 @@
-use std::{collections::HashMap, str::FromStr, sync::{Arc, Mutex}};
+use std::{collections::HashMap, str::FromStr, sync::Arc};
 
-use cggmp21::{keygen::NonThresholdMsg, round_based::{self}, security_level::SecurityLevel128, supported_curves::Secp256k1, DataToSign, ExecutionId, PregeneratedPrimes};
+use cggmp21::{keygen::{NonThresholdMsg, ThresholdMsg}, round_based::{self}, security_level::SecurityLevel128, supported_curves::Secp256k1, ExecutionId, PregeneratedPrimes};
 use futures::StreamExt;
 use libp2p::{gossipsub, swarm::SwarmEvent};
 use rand::RngCore;
@@ -8,15 +8,16 @@ use rand_core::OsRng;
 use sha2::{Digest, Sha256};
 use std::error::Error;
 
-use crate::off_chain::network::{sink::OutgoingSink, stream::IncomingStream};
-
-use super::network::{behaviour::MyBehaviourEvent, hash_map::{PARTY_TO_PEER_MAP, PEER_TO_PARTY_MAP}, setup::NetworkSetup};
+use super::signing::SigningInput;
+use super::network::{behaviour::MyBehaviourEvent, node::{message_author, Node}, registry::PeerRegistry, session::ProtocolKind, setup::{NetworkOptions, NetworkSetup}};
 use ark_ff::{BigInt, BigInteger};
-use cggmp21::{generic_ec::{curves::secp256k1::SecretScalar, NonZero, Scalar}, key_share::{DirtyAuxInfo, Valid, Validate}, IncompleteKeyShare, KeyShare};
+use cggmp21::{generic_ec::{curves::secp256k1::SecretScalar, NonZero, Point, Scalar}, signing::Presignature, key_share::{DirtyAuxInfo, Valid, Validate}, IncompleteKeyShare, KeyShare};
 pub struct MpcCurvy{
     network_setup: NetworkSetup,
+    registry: Arc<PeerRegistry>,
     n: u16,
     local_party_id: u16,
+    t: Option<u16>,
 }
 
 impl MpcCurvy{
@@ -27,19 +28,15 @@ impl MpcCurvy{
         self.network_setup.swarm.behaviour_mut().gossipsub.publish(self.network_setup.broadcast_topic.clone(), my_nonce.clone()).unwrap();
     
         let mut seen: HashMap<String, Vec<u8>> = HashMap::new();
-        seen.insert(PARTY_TO_PEER_MAP.get(&self.local_party_id).unwrap().to_string(), my_nonce);
+        let local_peer = self.registry.peer(self.local_party_id).expect("Local party is in the registry");
+        seen.insert(local_peer.peer_id.to_string(), my_nonce);
     
         while seen.len() < self.n as usize{
-            match self.network_setup.swarm.select_next_some().await{
-                SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Message { propagation_source, message_id: _, message })) =>{
-                    if message.topic == self.network_setup.broadcast_topic.clone().hash() && message.data.len() == 16 {
-                        let peer_id = propagation_source.to_string();
-                        if PEER_TO_PARTY_MAP.contains_key(&peer_id) {
-                            seen.insert(peer_id.clone(), message.data);
-                        }
-                    }
-                }
-                _ => {}
+            if let SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Message { propagation_source, message_id: _, message })) = self.network_setup.swarm.select_next_some().await
+                && message.topic == self.network_setup.broadcast_topic.clone().hash() && message.data.len() == 16
+                && let Some(author) = message_author(&self.registry, &propagation_source, &message) {
+                let author_peer = self.registry.peer(author).expect("Author is in the registry").peer_id;
+                seen.insert(author_peer.to_string(), message.data);
             }
         }
     
@@ -56,67 +53,114 @@ impl MpcCurvy{
         exec_id.to_vec()
     }
     
-    pub fn update_shares_and_complete(incomplete_key_share: IncompleteKeyShare<Secp256k1>, b: BigInt<4>, aux_info: Valid<DirtyAuxInfo>) -> Result<cggmp21::KeyShare<Secp256k1, SecurityLevel128>, Box<dyn Error>>{
-        let mut dirty_shares = incomplete_key_share.into_inner();
-        
+    fn tweak_scalar(b: BigInt<4>) -> Result<NonZero<Scalar<Secp256k1>>, Box<dyn Error>>{
         let b_bytes = b.to_bytes_be();
-        let b_slice: [u8; 32] = b_bytes.try_into().unwrap();
+        let b_slice: [u8; 32] = b_bytes.try_into().map_err(|_| "Tweak must be 32 bytes")?;
         let b_scalar: Scalar<Secp256k1> = Scalar::<Secp256k1>::from_be_bytes_mod_order(b_slice);
-        let b_nz = NonZero::from_scalar(b_scalar).unwrap();
-      
-        dirty_shares.x = NonZero::from_secret_scalar(SecretScalar::from_be_bytes((&dirty_shares.x * &b_nz).into_inner().to_be_bytes().as_bytes())?).unwrap();
-    
+        Ok(NonZero::from_scalar(b_scalar).ok_or("Tweak is zero")?)
+    }
+
+    /// Public key of the stealth address, i.e. the shared public key once tweaked by `b`
+    pub fn tweaked_public_key(incomplete_key_share: &IncompleteKeyShare<Secp256k1>, b: BigInt<4>) -> Result<Point<Secp256k1>, Box<dyn Error>>{
+        let b_nz = Self::tweak_scalar(b)?;
+        Ok(*incomplete_key_share.shared_public_key * b_nz)
+    }
+
+    /// Adapts a presignature of the untweaked key to the key tweaked by `b`: chi is an additive share of k*x
+    pub fn tweak_presignature(presignature: Presignature<Secp256k1>, b: BigInt<4>) -> Result<Presignature<Secp256k1>, Box<dyn Error>>{
+        let b_nz = Self::tweak_scalar(b)?;
+        let mut chi = presignature.chi.as_ref() * b_nz;
+        Ok(Presignature { R: presignature.R, k: presignature.k, chi: SecretScalar::new(&mut chi) })
+    }
+
+    /// Tweaks the key share by `b`, threshold or not
+    ///
+    /// The tweaked key is `b*x`, so every share of the polynomial is multiplied by `b`: the secret share,
+    /// all public shares and the shared public key. The VSS indexes and the threshold are left as they are,
+    /// `b*f` is still a polynomial of the same degree evaluated at the same points.
+    pub fn tweak_key_share(incomplete_key_share: IncompleteKeyShare<Secp256k1>, b: BigInt<4>) -> Result<IncompleteKeyShare<Secp256k1>, Box<dyn Error>>{
+        let mut dirty_shares = incomplete_key_share.into_inner();
+
+        let b_nz = Self::tweak_scalar(b)?;
+
+        let mut x = (&dirty_shares.x * b_nz).into_inner();
+        dirty_shares.x = NonZero::from_secret_scalar(SecretScalar::new(&mut x)).ok_or("Tweaked share is zero")?;
+
         for pub_share in &mut dirty_shares.key_info.public_shares{
-            *pub_share = NonZero::from_point(**pub_share * &b_nz).unwrap()
+            *pub_share *= b_nz;
         }
-    
-        dirty_shares.key_info.shared_public_key = dirty_shares.key_info.shared_public_key* &b_nz;
-    
-        Ok(KeyShare::from_parts((dirty_shares.validate()?, aux_info))?)
-    
+
+        dirty_shares.key_info.shared_public_key *= b_nz;
+
+        // Checks the public shares against the shared public key, by interpolation for threshold keys
+        Ok(dirty_shares.validate().map_err(|e| e.into_error())?)
+    }
+
+    pub fn update_shares_and_complete(incomplete_key_share: IncompleteKeyShare<Secp256k1>, b: BigInt<4>, aux_info: Valid<DirtyAuxInfo>) -> Result<cggmp21::KeyShare<Secp256k1, SecurityLevel128>, Box<dyn Error>>{
+        let key_share = Self::tweak_key_share(incomplete_key_share, b)?;
+        Ok(KeyShare::from_parts((key_share, aux_info))?)
     }
     
-    pub async fn new(local_party_id: u16, n: u16) -> Result<MpcCurvy, Box<dyn Error>>{
-        let network_setup = NetworkSetup::setup_swarm(local_party_id, n).await?;
-        Ok(MpcCurvy { network_setup, n, local_party_id})
+    pub async fn new(local_party_id: u16, registry: Arc<PeerRegistry>, options: &NetworkOptions) -> Result<MpcCurvy, Box<dyn Error>>{
+        let network_setup = NetworkSetup::setup_swarm(local_party_id, &registry, options).await?;
+        Ok(MpcCurvy { network_setup, n: registry.n(), registry, local_party_id, t: None })
+    }
+
+    /// Generates a t-of-n key instead of an n-of-n one
+    pub fn set_threshold(mut self, t: u16) -> MpcCurvy{
+        self.t = Some(t);
+        self
     }
     
-    pub async fn run(mut self) -> Result<(), Box<dyn Error>>{
+    pub async fn run(mut self, signing_input: SigningInput) -> Result<(), Box<dyn Error>>{
 
         let exec_id = self.gen_exec_id().await;
         let eid = ExecutionId::new(&exec_id);
       
-        let swarm = Arc::new(Mutex::new(self.network_setup.swarm));
-    
-        let incoming: IncomingStream<NonThresholdMsg<Secp256k1, SecurityLevel128, Sha256>> = IncomingStream::new(Arc::clone(&swarm), self.network_setup.my_topic.clone(), self.network_setup.broadcast_topic.clone());
-        let outgoing: OutgoingSink<NonThresholdMsg<Secp256k1, SecurityLevel128, Sha256>> = OutgoingSink::new(Arc::clone(&swarm), self.network_setup.broadcast_topic.clone());
+        let node = Node::from_setup(self.network_setup, self.local_party_id, Arc::clone(&self.registry));
+        // Prime search takes long, it runs on a blocking thread while the key is generated
+        let primes = tokio::task::spawn_blocking(|| PregeneratedPrimes::<SecurityLevel128>::generate(&mut OsRng));
     
-        let delivery = (incoming, outgoing); 
-        let party = round_based::MpcParty::connected(delivery);
+        let session = node.session(ProtocolKind::Keygen, &exec_id)?;
     
         println!("Generating key shares...");
-        let incomplete_key_share = cggmp21::keygen::<Secp256k1>(eid, self.local_party_id, self.n)
-            .start(&mut OsRng, party)
-            .await?;
+        let incomplete_key_share = match self.t{
+            Some(t) => {
+                let delivery = session.delivery::<ThresholdMsg<Secp256k1, SecurityLevel128, Sha256>>();
+                cggmp21::keygen::<Secp256k1>(eid, self.local_party_id, self.n)
+                    .set_threshold(t)
+                    .enforce_reliable_broadcast(false)
+                    .start(&mut OsRng, round_based::MpcParty::connected(delivery))
+                    .await?
+            }
+            None => {
+                let delivery = session.delivery::<NonThresholdMsg<Secp256k1, SecurityLevel128, Sha256>>();
+                cggmp21::keygen::<Secp256k1>(eid, self.local_party_id, self.n)
+                    .enforce_reliable_broadcast(false)
+                    .start(&mut OsRng, round_based::MpcParty::connected(delivery))
+                    .await?
+            }
+        };
     
+        session.finish();
         println!("Key shares generated...");
     
-        let incoming = IncomingStream::new(Arc::clone(&swarm), self.network_setup.my_topic.clone(), self.network_setup.broadcast_topic.clone());
-        let outgoing = OutgoingSink::new(Arc::clone(&swarm), self.network_setup.broadcast_topic.clone());
+        let session = node.session(ProtocolKind::AuxInfo, &exec_id)?;
+        let party = round_based::MpcParty::connected(session.delivery());
     
-        let delivery = (incoming, outgoing); 
-        let party = round_based::MpcParty::connected(delivery);
-    
-        let pregenerated_primes: PregeneratedPrimes<SecurityLevel128> = cggmp21::PregeneratedPrimes::generate(&mut OsRng);
+        let pregenerated_primes = primes.await?;
     
         println!("Generating aux info...");
         let aux_info = cggmp21::aux_info_gen(eid, self.local_party_id, self.n, pregenerated_primes)
+            .enforce_reliable_broadcast(false)
             .start(&mut OsRng, party)
             .await?;
+        session.finish();
         println!("Aux info generated...");
     
     
         let b = BigInt::from_str("4").unwrap();
+        let public_key = Self::tweaked_public_key(&incomplete_key_share, b)?;
         let key_share = Self::update_shares_and_complete(incomplete_key_share, b, aux_info)?;
     
         let mut parties_indexes_at_keygen = vec!(); 
@@ -124,20 +168,74 @@ impl MpcCurvy{
             parties_indexes_at_keygen.push(i);
         }
 
-        let data_to_sign: DataToSign<Secp256k1> = cggmp21::DataToSign::digest::<Sha256>(b"hello world"); 
-    
-        let incoming = IncomingStream::new(Arc::clone(&swarm), self.network_setup.my_topic.clone(), self.network_setup.broadcast_topic.clone());
-        let outgoing = OutgoingSink::new(Arc::clone(&swarm), self.network_setup.broadcast_topic.clone());
-    
-        let delivery = (incoming, outgoing); 
-        let party = round_based::MpcParty::connected(delivery);
+        let session = node.session(ProtocolKind::Signing, &exec_id)?;
+        let party = round_based::MpcParty::connected(session.delivery());
     
         println!("Signing...");
-        let _signature = cggmp21::signing(eid, self.local_party_id, &parties_indexes_at_keygen, &key_share)
-            .sign(&mut OsRng, party, data_to_sign)
+        let signature = cggmp21::signing(eid, self.local_party_id, &parties_indexes_at_keygen, &key_share)
+            .sign(&mut OsRng, party, signing_input.data_to_sign())
             .await?;
+        session.finish();
+        signature.verify(&public_key, &signing_input.data_to_sign())?;
         println!("Signed!");
     
         Ok(())
     }
 }
+
+
+#[cfg(test)]
+mod mpc_curvy_tests {
+    use cggmp21::key_share::{DirtyIncompleteKeyShare, DirtyKeyInfo, VssSetup};
+
+    use super::*;
+
+    // 2-of-3 shares of `secret` at indexes 1, 2, 3
+    fn threshold_shares(secret: &Scalar<Secp256k1>) -> Vec<IncompleteKeyShare<Secp256k1>>{
+        let slope = Scalar::<Secp256k1>::random(&mut OsRng);
+        let indexes: Vec<NonZero<Scalar<Secp256k1>>> = (1..=3u64)
+            .map(|i| NonZero::from_scalar(Scalar::from(i)).unwrap())
+            .collect();
+        let xs: Vec<Scalar<Secp256k1>> = indexes.iter().map(|i| secret + slope * i).collect();
+        let public_shares: Vec<_> = xs.iter().map(|x| NonZero::from_point(Point::generator() * x).unwrap()).collect();
+
+        (0..3u16).map(|i| DirtyIncompleteKeyShare {
+            i,
+            key_info: DirtyKeyInfo {
+                curve: Default::default(),
+                shared_public_key: NonZero::from_point(Point::generator() * secret).unwrap(),
+                public_shares: public_shares.clone(),
+                vss_setup: Some(VssSetup { min_signers: 2, I: indexes.clone() }),
+            },
+            x: NonZero::from_secret_scalar(SecretScalar::new(&mut xs[usize::from(i)].clone())).unwrap(),
+        }.validate().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_tweak_threshold_key_share() {
+        let secret = Scalar::<Secp256k1>::random(&mut OsRng);
+        let b = BigInt::from_str("4").unwrap();
+
+        let tweaked: Vec<_> = threshold_shares(&secret).into_iter()
+            .map(|share| {
+                let public_key = MpcCurvy::tweaked_public_key(&share, b).unwrap();
+                let tweaked = MpcCurvy::tweak_key_share(share, b).unwrap();
+                assert_eq!(*tweaked.shared_public_key, public_key);
+                assert_eq!(tweaked.vss_setup.as_ref().unwrap().min_signers, 2);
+                tweaked
+            })
+            .collect();
+
+        // Any two tweaked shares interpolate to b*secret, at indexes 2 and 3: 3*x2 - 2*x3
+        let x2: &Scalar<Secp256k1> = tweaked[1].x.as_ref();
+        let x3: &Scalar<Secp256k1> = tweaked[2].x.as_ref();
+        assert_eq!(Scalar::from(3u64) * x2 - Scalar::from(2u64) * x3, Scalar::from(4u64) * secret);
+    }
+
+    #[test]
+    fn test_zero_tweak_is_rejected() {
+        let secret = Scalar::<Secp256k1>::random(&mut OsRng);
+        let share = threshold_shares(&secret).remove(0);
+        assert!(MpcCurvy::tweak_key_share(share, BigInt::from_str("0").unwrap()).is_err());
+    }
+}
\ No newline at end of file
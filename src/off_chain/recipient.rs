@@ -8,8 +8,8 @@ use serde::{Deserialize, Serialize};
 
 use crate::off_chain::{common::{compute_viewtag, get_first_coordinate, stealth_pub_key_to_address}, utils::{deserialize_affine_point, deserialize_field_element, deserialize_secret_key}};
 
-pub fn scan(request: &String) -> Result<String, Box<dyn Error>>{
-    let request: RecipientRequest = serde_json::from_str(&request)?; 
+pub fn scan(request: &str) -> Result<String, Box<dyn Error>>{
+    let request: RecipientRequest = serde_json::from_str(request)?; 
 
     assert!(request.viewtags.len() == request.ephemeral_pub_key_reg.len());
 
@@ -28,7 +28,7 @@ pub fn scan(request: &String) -> Result<String, Box<dyn Error>>{
         let computed_viewtag = compute_viewtag(&v_r_product, request.view_tag_version)?; 
   
         if *viewtag == computed_viewtag{      
-            let ss =  Bn254::pairing(&v_r_product, &g2).0;
+            let ss =  Bn254::pairing(v_r_product, g2).0;
             let b = get_first_coordinate(&ss);
 
             let stealth_sk = compute_stealth_priv_key(&b, spending_sk)?; 
@@ -7,8 +7,8 @@ use serde::{Deserialize, Serialize};
 
 use super::{common::{compute_viewtag, get_first_coordinate, stealth_pub_key_to_address}, utils::{deserialize_affine_point, deserialize_secp_pk, generate_bn254_key_pair, serialize_affine_point, serialize_field_element, serialize_secp_pk}};
 
-pub fn send(request: &String) -> Result<String, Box<dyn Error>>{
-    let request: SenderRequest = serde_json::from_str(&request)?;
+pub fn send(request: &str) -> Result<String, Box<dyn Error>>{
+    let request: SenderRequest = serde_json::from_str(request)?;
 
     let viewing_pub_key = deserialize_affine_point(&request.viewing_pub_key)?; 
     let spending_pub_key = deserialize_secp_pk(&request.spending_pub_key)?;
@@ -50,7 +50,7 @@ fn compute_stealth_pub_key(x: &PublicKey, b: &BigInt<4>) -> Result<PublicKey, Bo
 fn compute_shared_secret(ephemeral_priv_key: &Fr, viewing_pub_key: &G1Affine) -> (G1Affine, Fq12){
     let r_times_v = ((*viewing_pub_key)*ephemeral_priv_key).into_affine(); 
     let g2 = G2Affine::generator(); 
-    (r_times_v, Bn254::pairing(&r_times_v, &g2).0)
+    (r_times_v, Bn254::pairing(r_times_v, g2).0)
 }
 
 #[derive(Deserialize,Serialize)]
@@ -91,7 +91,7 @@ mod sap_private_tests {
 
         let product = (ephemeral_pk*viewing_sk).into_affine(); 
         let g2 = G2Affine::generator(); 
-        let ss2 =  Bn254::pairing(&product, &g2).0; 
+        let ss2 =  Bn254::pairing(product, g2).0; 
 
         assert!(ss1 == ss2);
     }
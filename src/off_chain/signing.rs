@@ -0,0 +1,110 @@
+use std::{error::Error, fmt};
+
+use cggmp21::{generic_ec::Scalar, supported_curves::Secp256k1, DataToSign};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+
+use super::{eip712::TypedData, ethereum::hash_personal_message};
+
+/// Hash applied to a message before it is signed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm{
+    Keccak256,
+    Sha256,
+    /// `personal_sign`: Keccak-256 of the message behind the EIP-191 prefix
+    Eip191,
+}
+
+/// What the parties sign: a digest computed by the caller, or a message hashed here
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SigningInput{
+    Prehashed([u8; 32]),
+    Message{ message: Vec<u8>, hash: HashAlgorithm },
+}
+
+#[derive(Debug)]
+pub struct InvalidSigningInput(pub String);
+
+impl fmt::Display for InvalidSigningInput{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid data to sign: {}", self.0)
+    }
+}
+
+impl Error for InvalidSigningInput{}
+
+impl SigningInput{
+    /// Builds the input from its hex encoded form, exactly one of `digest` and `message` must be given
+    pub fn from_hex(digest: Option<&str>, message: Option<&str>, hash: Option<HashAlgorithm>) -> Result<SigningInput, InvalidSigningInput>{
+        match (digest, message){
+            (Some(digest), None) => {
+                if hash.is_some(){
+                    return Err(InvalidSigningInput("a hash algorithm cannot be applied to a prehashed digest".to_string()));
+                }
+                let digest: [u8; 32] = decode_hex(digest)?
+                    .try_into()
+                    .map_err(|_| InvalidSigningInput("digest must be 32 bytes".to_string()))?;
+                Ok(SigningInput::Prehashed(digest))
+            }
+            (None, Some(message)) => {
+                let hash = hash.ok_or_else(|| InvalidSigningInput("a hash algorithm is required to sign a message".to_string()))?;
+                Ok(SigningInput::Message { message: decode_hex(message)?, hash })
+            }
+            (Some(_), Some(_)) => Err(InvalidSigningInput("either a digest or a message must be given, not both".to_string())),
+            (None, None) => Err(InvalidSigningInput("a digest or a message is required".to_string())),
+        }
+    }
+
+    /// Signs the EIP-712 hash of the typed data
+    pub fn from_typed_data(typed_data: &TypedData) -> Result<SigningInput, InvalidSigningInput>{
+        let digest = typed_data.signing_hash().map_err(|e| InvalidSigningInput(e.to_string()))?;
+        Ok(SigningInput::Prehashed(digest))
+    }
+
+    /// The 32-byte digest the signature is computed over
+    pub fn digest(&self) -> [u8; 32]{
+        match self{
+            SigningInput::Prehashed(digest) => *digest,
+            SigningInput::Message { message, hash: HashAlgorithm::Keccak256 } => Keccak256::digest(message).into(),
+            SigningInput::Message { message, hash: HashAlgorithm::Sha256 } => Sha256::digest(message).into(),
+            SigningInput::Message { message, hash: HashAlgorithm::Eip191 } => hash_personal_message(message),
+        }
+    }
+
+    pub fn data_to_sign(&self) -> DataToSign<Secp256k1>{
+        DataToSign::from_scalar(Scalar::from_be_bytes_mod_order(self.digest()))
+    }
+}
+
+fn decode_hex(x: &str) -> Result<Vec<u8>, InvalidSigningInput>{
+    let x = x.strip_prefix("0x").unwrap_or(x);
+    hex::decode(x).map_err(|e| InvalidSigningInput(e.to_string()))
+}
+
+#[cfg(test)]
+mod signing_tests {
+    use super::*;
+
+    #[test]
+    fn test_message_digests() {
+        let keccak = SigningInput::from_hex(None, Some(""), Some(HashAlgorithm::Keccak256)).unwrap();
+        assert_eq!(hex::encode(keccak.digest()), "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470");
+
+        let sha256 = SigningInput::Message { message: b"hello world".to_vec(), hash: HashAlgorithm::Sha256 };
+        assert_eq!(sha256.data_to_sign().to_scalar(), DataToSign::<Secp256k1>::digest::<Sha256>(b"hello world").to_scalar());
+    }
+
+    #[test]
+    fn test_invalid_inputs() {
+        let digest = format!("0x{}", hex::encode([1u8; 32]));
+        assert_eq!(SigningInput::from_hex(Some(&digest), None, None).unwrap(), SigningInput::Prehashed([1u8; 32]));
+
+        assert!(SigningInput::from_hex(Some("abcd"), None, None).is_err());
+        assert!(SigningInput::from_hex(Some(&digest), None, Some(HashAlgorithm::Sha256)).is_err());
+        assert!(SigningInput::from_hex(None, Some("abcd"), None).is_err());
+        assert!(SigningInput::from_hex(Some(&digest), Some("abcd"), Some(HashAlgorithm::Sha256)).is_err());
+        assert!(SigningInput::from_hex(None, None, None).is_err());
+    }
+}
\ No newline at end of file
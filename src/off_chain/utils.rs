@@ -33,12 +33,12 @@ pub fn serialize_field_element(x: &Fr) -> String{
 }
 
 pub fn deserialize_field_element(x: &String) -> Result<Fr, Box<dyn Error>>{
-    let x_bytes = hex::decode(&x)?;
+    let x_bytes = hex::decode(x)?;
     Ok(Fr::from_be_bytes_mod_order(&x_bytes))
 }
 
 pub fn deserialize_secret_key(x: &String) ->Result<SecretKey, Box<dyn Error>>{
-    let x_bytes = hex::decode(&x)?;
+    let x_bytes = hex::decode(x)?;
     assert!(x_bytes.len() == 32);
    
     let mut scalar_bytes = [0u8; 32];
@@ -51,7 +51,7 @@ pub fn deserialize_secret_key(x: &String) ->Result<SecretKey, Box<dyn Error>>{
 
 pub fn serialize_secret_key(x: &SecretKey) -> String{
     let x_bytes = x.secret_bytes(); 
-    hex::encode(&x_bytes)
+    hex::encode(x_bytes)
 }
 
 pub fn generate_bn254_key_pair() -> (Fr, G1Affine) {
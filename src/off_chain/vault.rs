@@ -0,0 +1,357 @@
+use std::{error::Error, fmt, fs, io, path::{Path, PathBuf}};
+
+use argon2::Argon2;
+use cggmp21::{key_share::AuxInfo, security_level::SecurityLevel128, supported_curves::Secp256k1, IncompleteKeyShare, PregeneratedPrimes};
+use chacha20poly1305::{aead::{Aead, Payload}, AeadCore, ChaCha20Poly1305, Key, KeyInit, Nonce};
+use rand::RngCore;
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// File layout: MAGIC | VERSION | KDF | SALT | NONCE | ciphertext
+const MAGIC: &[u8; 4] = b"MPCV";
+const VERSION: u8 = 1;
+const KDF_RAW: u8 = 0;
+const KDF_ARGON2ID: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = MAGIC.len() + 2 + SALT_LEN + NONCE_LEN;
+const SHARE_EXTENSION: &str = "share";
+const PRIMES_EXTENSION: &str = "primes";
+
+/// Key-encryption key protecting the key shares at rest
+pub enum KeyEncryptionKey{
+    Raw([u8; 32]),
+    Passphrase(String),
+}
+
+/// Secret material a party holds for one distributed key
+#[derive(Serialize, Deserialize)]
+pub struct KeyRecord{
+    pub key_share: IncompleteKeyShare<Secp256k1>,
+    pub aux_info: Option<AuxInfo<SecurityLevel128>>,
+    /// Number of refreshes the share went through, 0 right after keygen
+    #[serde(default)]
+    pub epoch: u64,
+    #[serde(default)]
+    pub refresh_schedule: Option<RefreshSchedule>,
+}
+
+/// Periodic refresh of a key, every party runs it at the same wall clock time
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RefreshSchedule{
+    pub interval_secs: u64,
+    /// Unix time of the next refresh, always a multiple of the interval
+    pub next_refresh_at: u64,
+}
+
+impl RefreshSchedule{
+    pub fn new(interval_secs: u64, now: u64) -> RefreshSchedule{
+        RefreshSchedule { interval_secs, next_refresh_at: (now / interval_secs + 1) * interval_secs }
+    }
+
+    pub fn is_due(&self, now: u64) -> bool{
+        now >= self.next_refresh_at
+    }
+
+    /// Moves to the first slot after `now`, slots missed while the node was down are skipped
+    pub fn advance(&mut self, now: u64){
+        *self = RefreshSchedule::new(self.interval_secs, now.max(self.next_refresh_at));
+    }
+}
+
+impl KeyRecord{
+    pub fn new(key_share: IncompleteKeyShare<Secp256k1>) -> KeyRecord{
+        KeyRecord { key_share, aux_info: None, epoch: 0, refresh_schedule: None }
+    }
+
+    /// Key id all parties derive for the same key, from the shared public key
+    pub fn key_id(&self) -> String{
+        key_id_of(&self.key_share)
+    }
+
+    pub fn public_key(&self) -> String{
+        hex::encode(self.key_share.shared_public_key.to_bytes(true))
+    }
+}
+
+pub fn key_id_of(key_share: &IncompleteKeyShare<Secp256k1>) -> String{
+    let digest = Sha256::digest(key_share.shared_public_key.to_bytes(true));
+    hex::encode(&digest[..16])
+}
+
+#[derive(Debug)]
+pub enum VaultError{
+    NotFound(String),
+    InvalidKeyId(String),
+    UnsupportedFormat,
+    Decryption,
+    Serialization(serde_json::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for VaultError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self{
+            VaultError::NotFound(key_id) => write!(f, "key {} not found", key_id),
+            VaultError::InvalidKeyId(key_id) => write!(f, "invalid key id {}", key_id),
+            VaultError::UnsupportedFormat => write!(f, "unsupported key file format"),
+            VaultError::Decryption => write!(f, "cannot decrypt key file, wrong key-encryption key or corrupted file"),
+            VaultError::Serialization(e) => write!(f, "cannot serialize key record: {}", e),
+            VaultError::Io(e) => write!(f, "key vault io error: {}", e),
+        }
+    }
+}
+
+impl Error for VaultError{}
+
+impl From<io::Error> for VaultError{
+    fn from(e: io::Error) -> Self {
+        VaultError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for VaultError{
+    fn from(e: serde_json::Error) -> Self {
+        VaultError::Serialization(e)
+    }
+}
+
+/// Stores every key record in its own encrypted file, named after the key id
+pub struct KeyVault{
+    dir: PathBuf,
+    kek: KeyEncryptionKey,
+}
+
+impl KeyVault{
+    pub fn open(dir: impl Into<PathBuf>, kek: KeyEncryptionKey) -> Result<KeyVault, VaultError>{
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(KeyVault { dir, kek })
+    }
+
+    pub fn store(&self, key_id: &str, record: &KeyRecord) -> Result<(), VaultError>{
+        let path = self.path(key_id)?;
+        // JSON rather than bincode: key shares skip empty optional fields, which bincode cannot read back
+        let plaintext = serde_json::to_vec(record)?;
+        self.write_sealed(&path, key_id, &plaintext)
+    }
+
+    pub fn load(&self, key_id: &str) -> Result<KeyRecord, VaultError>{
+        let path = self.path(key_id)?;
+        let plaintext = self.read_sealed(&path, key_id)?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+
+    pub fn contains(&self, key_id: &str) -> bool{
+        self.path(key_id).map(|path| path.exists()).unwrap_or(false)
+    }
+
+    /// Keeps pregenerated Paillier primes across restarts, they are as secret as the key shares
+    pub fn store_primes(&self, id: &str, primes: &PregeneratedPrimes<SecurityLevel128>) -> Result<(), VaultError>{
+        let path = self.primes_path(id)?;
+        let plaintext = serde_json::to_vec(primes)?;
+        self.write_sealed(&path, &format!("primes/{}", id), &plaintext)
+    }
+
+    pub fn load_primes(&self, id: &str) -> Result<PregeneratedPrimes<SecurityLevel128>, VaultError>{
+        let path = self.primes_path(id)?;
+        let plaintext = self.read_sealed(&path, &format!("primes/{}", id))?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+
+    pub fn remove_primes(&self, id: &str) -> Result<(), VaultError>{
+        match fs::remove_file(self.primes_path(id)?){
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Ids of every stored set of primes
+    pub fn primes_ids(&self) -> Result<Vec<String>, VaultError>{
+        self.ids_with_extension(PRIMES_EXTENSION)
+    }
+
+    pub fn key_ids(&self) -> Result<Vec<String>, VaultError>{
+        self.ids_with_extension(SHARE_EXTENSION)
+    }
+
+    fn ids_with_extension(&self, extension: &str) -> Result<Vec<String>, VaultError>{
+        let mut ids = vec![];
+        for entry in fs::read_dir(&self.dir)?{
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == extension)
+                && let Some(id) = path.file_stem().and_then(|stem| stem.to_str()){
+                ids.push(id.to_string());
+            }
+        }
+        Ok(ids)
+    }
+
+    fn write_sealed(&self, path: &Path, name: &str, plaintext: &[u8]) -> Result<(), VaultError>{
+        let (kdf, salt) = match self.kek{
+            KeyEncryptionKey::Raw(_) => (KDF_RAW, [0u8; SALT_LEN]),
+            KeyEncryptionKey::Passphrase(_) => {
+                let mut salt = [0u8; SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                (KDF_ARGON2ID, salt)
+            }
+        };
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        let mut file = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+        file.extend_from_slice(MAGIC);
+        file.push(VERSION);
+        file.push(kdf);
+        file.extend_from_slice(&salt);
+        file.extend_from_slice(&nonce);
+
+        let cipher = ChaCha20Poly1305::new(&self.derive_key(kdf, &salt)?);
+        let aad = associated_data(&file, name);
+        let ciphertext = cipher.encrypt(&nonce, Payload { msg: plaintext, aad: &aad })
+            .map_err(|_| VaultError::Decryption)?;
+        file.extend_from_slice(&ciphertext);
+
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, &file)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    fn read_sealed(&self, path: &Path, name: &str) -> Result<Vec<u8>, VaultError>{
+        let file = match fs::read(path){
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(VaultError::NotFound(name.to_string())),
+            Err(e) => return Err(e.into()),
+        };
+
+        if file.len() < HEADER_LEN || &file[..MAGIC.len()] != MAGIC || file[MAGIC.len()] != VERSION{
+            return Err(VaultError::UnsupportedFormat);
+        }
+        let kdf = file[MAGIC.len() + 1];
+        let salt = &file[MAGIC.len() + 2..MAGIC.len() + 2 + SALT_LEN];
+        let nonce = Nonce::from_slice(&file[HEADER_LEN - NONCE_LEN..HEADER_LEN]);
+
+        let cipher = ChaCha20Poly1305::new(&self.derive_key(kdf, salt)?);
+        let aad = associated_data(&file[..HEADER_LEN], name);
+        cipher.decrypt(nonce, Payload { msg: &file[HEADER_LEN..], aad: &aad })
+            .map_err(|_| VaultError::Decryption)
+    }
+
+    fn path(&self, key_id: &str) -> Result<PathBuf, VaultError>{
+        Ok(self.dir.join(format!("{}.{}", checked_id(key_id)?, SHARE_EXTENSION)))
+    }
+
+    fn primes_path(&self, id: &str) -> Result<PathBuf, VaultError>{
+        Ok(self.dir.join(format!("{}.{}", checked_id(id)?, PRIMES_EXTENSION)))
+    }
+
+    fn derive_key(&self, kdf: u8, salt: &[u8]) -> Result<Key, VaultError>{
+        match (&self.kek, kdf){
+            (KeyEncryptionKey::Raw(kek), KDF_RAW) => Ok(*Key::from_slice(kek)),
+            (KeyEncryptionKey::Passphrase(passphrase), KDF_ARGON2ID) => {
+                let mut key = Key::default();
+                Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut key)
+                    .map_err(|_| VaultError::Decryption)?;
+                Ok(key)
+            }
+            _ => Err(VaultError::Decryption),
+        }
+    }
+}
+
+// Ids end up in file names, only hex is accepted
+fn checked_id(id: &str) -> Result<&str, VaultError>{
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()){
+        return Err(VaultError::InvalidKeyId(id.to_string()));
+    }
+    Ok(id)
+}
+
+// Binds the ciphertext to its header and name, so a key file cannot be swapped for another one
+fn associated_data(header: &[u8], name: &str) -> Vec<u8>{
+    let mut aad = header.to_vec();
+    aad.extend_from_slice(name.as_bytes());
+    aad
+}
+
+#[cfg(test)]
+mod vault_tests {
+    use cggmp21::generic_ec::{NonZero, Point, SecretScalar};
+    use cggmp21::key_share::{DirtyIncompleteKeyShare, DirtyKeyInfo, Validate};
+
+    use super::*;
+
+    // Share of party 0 in a 2-of-2 additive key, as produced by non-threshold keygen
+    fn additive_share() -> IncompleteKeyShare<Secp256k1>{
+        let x = NonZero::<SecretScalar<Secp256k1>>::random(&mut OsRng);
+        let other_x = NonZero::<SecretScalar<Secp256k1>>::random(&mut OsRng);
+        let public_shares = vec![Point::generator() * &x, Point::generator() * &other_x];
+        DirtyIncompleteKeyShare {
+            i: 0,
+            key_info: DirtyKeyInfo {
+                curve: Default::default(),
+                shared_public_key: NonZero::from_point(public_shares[0] + public_shares[1]).unwrap(),
+                public_shares,
+                vss_setup: None,
+            },
+            x,
+        }.validate().unwrap()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf{
+        let dir = std::env::temp_dir().join(format!("mpc-vault-{}-{}", name, uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_store_and_load() {
+        let record = KeyRecord::new(additive_share());
+        let key_id = record.key_id();
+
+        let dir = temp_dir("roundtrip");
+        let vault = KeyVault::open(&dir, KeyEncryptionKey::Passphrase("correct horse".to_string())).unwrap();
+        vault.store(&key_id, &record).unwrap();
+
+        let loaded = vault.load(&key_id).unwrap();
+        assert_eq!(loaded.public_key(), record.public_key());
+        assert!(loaded.aux_info.is_none());
+        assert_eq!(vault.key_ids().unwrap(), vec![key_id.clone()]);
+
+        let wrong_passphrase = KeyVault::open(&dir, KeyEncryptionKey::Passphrase("wrong".to_string())).unwrap();
+        assert!(matches!(wrong_passphrase.load(&key_id), Err(VaultError::Decryption)));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_key_file_is_bound_to_key_id() {
+        let record = KeyRecord::new(additive_share());
+        let key_id = record.key_id();
+
+        let dir = temp_dir("binding");
+        let vault = KeyVault::open(&dir, KeyEncryptionKey::Raw([7u8; 32])).unwrap();
+        vault.store(&key_id, &record).unwrap();
+        fs::copy(dir.join(format!("{}.share", key_id)), dir.join("abcd.share")).unwrap();
+
+        assert!(matches!(vault.load("abcd"), Err(VaultError::Decryption)));
+        assert!(matches!(vault.load("../etc"), Err(VaultError::InvalidKeyId(_))));
+        assert!(matches!(vault.load("beef"), Err(VaultError::NotFound(_))));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_refresh_schedule_is_aligned_to_the_interval() {
+        let mut schedule = RefreshSchedule::new(3600, 7300);
+        assert_eq!(schedule.next_refresh_at, 10800);
+        assert!(!schedule.is_due(10799));
+        assert!(schedule.is_due(10800));
+
+        schedule.advance(10801);
+        assert_eq!(schedule.next_refresh_at, 14400);
+        schedule.advance(30000);
+        assert_eq!(schedule.next_refresh_at, 32400);
+    }
+}
@@ -1,11 +1,71 @@
 use serde::Serialize;
+use uuid::Uuid;
 
 #[derive(Serialize, Debug)]
 pub struct KeyGenerationResponse {
-    pub incomplete_key_share: String
+    pub key_id: String,
+    pub public_key: String
 }
 
 #[derive(Serialize, Debug)]
 pub struct SignTransactionResponse {
-    pub signature: String
+    pub digest: String,
+    pub r: String,
+    pub s: String,
+    pub v: u8,
+    pub y_parity: u8,
+    /// 65-byte `r || s || v`
+    pub signature: String,
+    pub der: String
+}
+
+#[derive(Serialize, Debug)]
+pub struct SignEthTransactionResponse {
+    pub raw_transaction: String,
+    pub tx_hash: String,
+    pub from: String
+}
+
+#[derive(Serialize, Debug)]
+pub struct AuxInfoResponse {
+    pub key_id: String,
+    pub public_key: String
+}
+
+#[derive(Serialize, Debug)]
+pub struct PresignResponse {
+    pub key_id: String,
+    pub presignature_ids: Vec<String>
+}
+
+#[derive(Serialize, Debug)]
+pub struct AvailablePresignature {
+    pub id: String,
+    pub expires_in_secs: u64
+}
+
+#[derive(Serialize, Debug)]
+pub struct PresignaturesResponse {
+    pub key_id: String,
+    pub presignatures: Vec<AvailablePresignature>
+}
+
+#[derive(Serialize, Debug)]
+pub struct RefreshResponse {
+    pub key_id: String,
+    pub public_key: String,
+    pub epoch: u64
+}
+
+#[derive(Serialize, Debug)]
+pub struct RefreshScheduleResponse {
+    pub key_id: String,
+    pub epoch: u64,
+    pub interval_secs: Option<u64>,
+    pub next_refresh_at: Option<u64>
+}
+
+#[derive(Serialize, Debug)]
+pub struct JobCreatedResponse {
+    pub job_id: Uuid
 }
\ No newline at end of file
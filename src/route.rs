@@ -1,24 +1,42 @@
+use std::sync::Arc;
+
 use axum::{
-    routing::get,
+    routing::{get, post},
     Router,
 };
+use mpc_service::off_chain::{network::node::Node, presignature::PresignaturePool, primes::PrimePool, vault::KeyVault};
 
 use crate::{
     handler::{
-        health_checker_handler, key_generation_handler, sign_transaction_handler
+        aux_info_job_handler, health_checker_handler, job_status_handler,
+        keygen_job_handler, presign_job_handler, presignatures_handler, refresh_job_handler,
+        refresh_schedule_handler, set_refresh_schedule_handler, sign_eth_transaction_job_handler,
+        sign_job_handler
     },
+    job::JobRegistry,
 };
 
-pub fn create_router() -> Router {
+#[derive(Clone)]
+pub struct AppState {
+    pub node: Arc<Node>,
+    pub jobs: Arc<JobRegistry>,
+    pub vault: Arc<KeyVault>,
+    pub presignatures: Arc<PresignaturePool>,
+    pub primes: Arc<PrimePool>,
+}
+
+pub fn create_router(state: AppState) -> Router {
 
     Router::new()
         .route("/healthchecker", get(health_checker_handler))
-        .route(
-            "/key-generation",
-            get(key_generation_handler),
-        )
-        .route(
-            "/sign-transaction",
-            get(sign_transaction_handler)
-        )
+        .route("/jobs/keygen", post(keygen_job_handler))
+        .route("/jobs/aux-info", post(aux_info_job_handler))
+        .route("/jobs/sign", post(sign_job_handler))
+        .route("/jobs/sign-eth-transaction", post(sign_eth_transaction_job_handler))
+        .route("/jobs/presign", post(presign_job_handler))
+        .route("/jobs/refresh", post(refresh_job_handler))
+        .route("/jobs/:job_id", get(job_status_handler))
+        .route("/keys/:key_id/presignatures", get(presignatures_handler))
+        .route("/keys/:key_id/refresh-schedule", get(refresh_schedule_handler).put(set_refresh_schedule_handler))
+        .with_state(state)
 }
\ No newline at end of file
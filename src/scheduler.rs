@@ -0,0 +1,60 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    handler::job_result,
+    job::{JobKind, JobTracer},
+    model::RefreshReqBody,
+    route::AppState,
+    service,
+};
+
+// How often the vault is scanned for keys due for a refresh
+const REFRESH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Refreshes every key whose schedule is due
+///
+/// Each party runs its own scheduler, they meet in the same protocol run because the execution id
+/// is derived from the key id and the scheduled time.
+pub async fn run_refresh_scheduler(state: AppState) {
+    let mut ticker = tokio::time::interval(REFRESH_CHECK_INTERVAL);
+    loop {
+        ticker.tick().await;
+
+        let key_ids = match state.vault.key_ids() {
+            Ok(key_ids) => key_ids,
+            Err(e) => {
+                println!("Cannot list keys to refresh: {}", e);
+                continue;
+            }
+        };
+
+        for key_id in key_ids {
+            let schedule = match state.vault.load(&key_id) {
+                Ok(record) => record.refresh_schedule,
+                Err(e) => {
+                    println!("Cannot load key {}: {}", key_id, e);
+                    continue;
+                }
+            };
+            let Some(schedule) = schedule.filter(|schedule| schedule.is_due(service::unix_now())) else {
+                continue;
+            };
+
+            let body = RefreshReqBody {
+                exec_id: format!("refresh/{}/{}", key_id, schedule.next_refresh_at),
+                key_id: key_id.clone(),
+            };
+            let job_id = state.jobs.create(JobKind::Refresh);
+            println!("Scheduled refresh of key {}, job {}", key_id, job_id);
+
+            let mut tracer = JobTracer::new(Arc::clone(&state.jobs), job_id);
+            let result = service::refresh_key(&state.node, &state.vault, &state.primes, &state.presignatures, &body, &mut tracer).await;
+            state.jobs.finish(&job_id, job_result(result));
+
+            // Advanced even after a failure, the parties meet again at the next slot
+            if let Err(e) = service::advance_refresh_schedule(&state.vault, &key_id, service::unix_now()) {
+                println!("Cannot advance refresh schedule of key {}: {}", key_id, e.message);
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,685 @@
+use std::{collections::BTreeMap, error::Error, time::{SystemTime, UNIX_EPOCH}};
+
+use ark_bn254::{Bn254, G2Affine};
+use ark_ec::pairing::Pairing;
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ff::BigInt;
+use axum::{http::StatusCode, response::{IntoResponse, Response}, Json};
+use cggmp21::generic_ec::{Point, Scalar};
+use cggmp21::keygen::ThresholdMsg;
+use cggmp21::key_refresh::NonThresholdMsg;
+use cggmp21::key_share::{AuxInfo, DirtyKeyShare, Validate};
+use cggmp21::round_based::{Outgoing, ProtocolMessage};
+use cggmp21::security_level::SecurityLevel128;
+use cggmp21::signing::{PartialSignature, Presignature, Signature};
+use cggmp21::supported_curves::Secp256k1;
+use cggmp21::{round_based, DataToSign, ExecutionId, IncompleteKeyShare, KeyShare};
+use futures::{SinkExt, StreamExt};
+
+use mpc_service::off_chain::common::{compute_viewtag, get_first_coordinate};
+use mpc_service::off_chain::ethereum::{Address, RecoverableSignature};
+use mpc_service::off_chain::network::{error::NetworkError, node::Node, session::{Participants, ProtocolKind, SessionAlreadyOpen}};
+use mpc_service::off_chain::presignature::{PresignatureError, PresignaturePool};
+use mpc_service::off_chain::primes::PrimePool;
+use mpc_service::off_chain::protocol::MpcCurvy;
+use mpc_service::off_chain::signing::SigningInput;
+use mpc_service::off_chain::utils::{deserialize_affine_point, deserialize_field_element};
+use mpc_service::off_chain::vault::{KeyRecord, KeyVault, RefreshSchedule, VaultError};
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sha3::{Digest, Keccak256};
+
+use crate::{
+    job::JobTracer,
+    model::{
+        AuxInfoReqBody, KeyGenerationReqBody, PresignReqBody, RefreshReqBody, RefreshScheduleReqBody,
+        SignEthTransactionReqBody, SignTransactionReqBody
+    },
+    response::{
+        AuxInfoResponse, AvailablePresignature, KeyGenerationResponse, PresignResponse, PresignaturesResponse,
+        RefreshResponse, RefreshScheduleResponse, SignEthTransactionResponse, SignTransactionResponse
+    },
+};
+
+// Upper bound on the presignatures one job generates, each one is a full protocol run
+const MAX_PRESIGNATURES_PER_JOB: u16 = 100;
+// A refresh regenerates aux info, which takes minutes, shorter intervals would never let the key rest
+const MIN_REFRESH_INTERVAL_SECS: u64 = 600;
+
+#[derive(Debug)]
+pub struct ServiceError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl ServiceError {
+    pub fn bad_request(message: impl Into<String>) -> ServiceError {
+        ServiceError { status: StatusCode::BAD_REQUEST, message: message.into() }
+    }
+
+    pub fn not_implemented(message: impl Into<String>) -> ServiceError {
+        ServiceError { status: StatusCode::NOT_IMPLEMENTED, message: message.into() }
+    }
+
+    pub fn unavailable(message: impl Into<String>) -> ServiceError {
+        ServiceError { status: StatusCode::SERVICE_UNAVAILABLE, message: message.into() }
+    }
+
+    pub fn internal(message: impl Into<String>) -> ServiceError {
+        ServiceError { status: StatusCode::INTERNAL_SERVER_ERROR, message: message.into() }
+    }
+
+    /// Failure of a cggmp21 protocol, with the network error that aborted it when there is one
+    pub fn protocol(context: &str, e: &(dyn Error + 'static)) -> ServiceError {
+        let mut cause = e.source();
+        while let Some(source) = cause {
+            if let Some(network) = source.downcast_ref::<NetworkError>() {
+                return ServiceError::internal(format!("{}: {}: {}", context, e, network));
+            }
+            cause = source.source();
+        }
+        ServiceError::internal(format!("{}: {}", context, e))
+    }
+}
+
+impl From<SessionAlreadyOpen> for ServiceError {
+    fn from(e: SessionAlreadyOpen) -> ServiceError {
+        ServiceError { status: StatusCode::CONFLICT, message: e.to_string() }
+    }
+}
+
+impl From<VaultError> for ServiceError {
+    fn from(e: VaultError) -> ServiceError {
+        let status = match e {
+            VaultError::NotFound(_) => StatusCode::NOT_FOUND,
+            VaultError::InvalidKeyId(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        ServiceError { status, message: e.to_string() }
+    }
+}
+
+impl From<PresignatureError> for ServiceError {
+    fn from(e: PresignatureError) -> ServiceError {
+        let status = match e {
+            PresignatureError::NotFound(_) => StatusCode::NOT_FOUND,
+            PresignatureError::Expired(_) => StatusCode::GONE,
+            PresignatureError::AlreadyUsed(_) => StatusCode::CONFLICT,
+            PresignatureError::WrongKey { .. } => StatusCode::BAD_REQUEST,
+        };
+        ServiceError { status, message: e.to_string() }
+    }
+}
+
+impl IntoResponse for ServiceError {
+    fn into_response(self) -> Response {
+        (self.status, Json(serde_json::json!({
+            "error": self.message
+        }))).into_response()
+    }
+}
+
+pub async fn generate_key(
+    node: &Node,
+    vault: &KeyVault,
+    primes: &PrimePool,
+    opts: &KeyGenerationReqBody,
+    tracer: &mut JobTracer,
+) -> Result<KeyGenerationResponse, ServiceError> {
+    let eid = ExecutionId::new(opts.exec_id.as_bytes());
+
+    let session = node.session(ProtocolKind::Keygen, opts.exec_id.as_bytes())?;
+    let delivery = session.delivery::<ThresholdMsg<Secp256k1, SecurityLevel128, Sha256>>();
+    let party = round_based::MpcParty::connected(delivery);
+
+    println!("Generating key shares...");
+    let incomplete_key_share = cggmp21::keygen::<Secp256k1>(eid, node.local_party_id, node.n)
+        .set_threshold(opts.t)
+        // Broadcasts are echoed by the network layer, the extra reliability round is not needed
+        .enforce_reliable_broadcast(false)
+        .set_progress_tracer(tracer.begin("keygen"))
+        .start(&mut OsRng, party)
+        .await
+        .map_err(|e| ServiceError::protocol("Key generation failed", &e))?;
+    session.finish();
+
+    println!("Key shares generated...");
+
+    let mut record = KeyRecord::new(incomplete_key_share);
+    let key_id = record.key_id();
+    vault.store(&key_id, &record)?;
+
+    // Generated once here and kept with the share, every signature of the key reuses it. The share is
+    // stored already, when aux info fails the client needs the key id to generate it again
+    let aux_info = run_aux_info_gen(node, primes, opts.exec_id.as_bytes(), tracer).await.map_err(|e| ServiceError {
+        message: format!("Key {} was generated without aux info, generate it with /jobs/aux-info: {}", key_id, e.message),
+        ..e
+    })?;
+    record.aux_info = Some(aux_info);
+    vault.store(&key_id, &record)?;
+
+    Ok(KeyGenerationResponse {
+        key_id,
+        public_key: record.public_key()
+    })
+}
+
+pub async fn generate_aux_info(
+    node: &Node,
+    vault: &KeyVault,
+    primes: &PrimePool,
+    opts: &AuxInfoReqBody,
+    tracer: &mut JobTracer,
+) -> Result<AuxInfoResponse, ServiceError> {
+    // Fail early on an unknown key, before the other parties are engaged in the protocol
+    let mut record = vault.load(&opts.key_id)?;
+
+    let aux_info = run_aux_info_gen(node, primes, opts.exec_id.as_bytes(), tracer).await?;
+
+    record.aux_info = Some(aux_info);
+    vault.store(&opts.key_id, &record)?;
+
+    Ok(AuxInfoResponse {
+        key_id: opts.key_id.clone(),
+        public_key: record.public_key()
+    })
+}
+
+pub async fn refresh_key(
+    node: &Node,
+    vault: &KeyVault,
+    primes: &PrimePool,
+    presignatures: &PresignaturePool,
+    opts: &RefreshReqBody,
+    tracer: &mut JobTracer,
+) -> Result<RefreshResponse, ServiceError> {
+    let mut record = vault.load(&opts.key_id)?;
+    let exec_id = opts.exec_id.as_bytes();
+
+    check_refreshable(&opts.key_id, &record)?;
+
+    // cggmp21 key refresh rotates additive shares and aux info in one run
+    let aux_info = cached_aux_info(record.aux_info.clone(), &opts.key_id)?;
+    let key_share = KeyShare::from_parts((record.key_share.clone(), aux_info))
+        .map_err(|e| ServiceError::internal(format!("Cannot complete key share: {}", e)))?;
+    let (key_share, aux_info) = run_key_refresh(node, primes, exec_id, &key_share, tracer).await?;
+
+    record.key_share = key_share;
+    record.aux_info = Some(aux_info);
+    record.epoch += 1;
+    vault.store(&opts.key_id, &record)?;
+    // Presignatures were issued from the previous shares
+    presignatures.discard(&opts.key_id);
+    println!("Key {} refreshed, epoch {}", opts.key_id, record.epoch);
+
+    Ok(RefreshResponse {
+        key_id: opts.key_id.clone(),
+        public_key: record.public_key(),
+        epoch: record.epoch,
+    })
+}
+
+/// Rejects keys the refresh cannot rotate, before a job is started for them
+pub fn check_refresh(vault: &KeyVault, key_id: &str) -> Result<(), ServiceError> {
+    check_refreshable(key_id, &vault.load(key_id)?)
+}
+
+// cggmp21 key refresh only supports non-threshold key shares
+fn check_refreshable(key_id: &str, record: &KeyRecord) -> Result<(), ServiceError> {
+    if record.key_share.vss_setup.is_some() {
+        return Err(ServiceError::not_implemented(format!("Key {} is a threshold key, its shares cannot be refreshed", key_id)));
+    }
+    Ok(())
+}
+
+pub fn refresh_schedule(vault: &KeyVault, key_id: &str) -> Result<RefreshScheduleResponse, ServiceError> {
+    let record = vault.load(key_id)?;
+    Ok(refresh_schedule_response(key_id, &record))
+}
+
+pub fn set_refresh_schedule(
+    vault: &KeyVault,
+    key_id: &str,
+    opts: &RefreshScheduleReqBody,
+) -> Result<RefreshScheduleResponse, ServiceError> {
+    let mut record = vault.load(key_id)?;
+    if opts.interval_secs.is_some() {
+        check_refreshable(key_id, &record)?;
+    }
+    record.refresh_schedule = match opts.interval_secs {
+        Some(interval_secs) if interval_secs < MIN_REFRESH_INTERVAL_SECS => {
+            return Err(ServiceError::bad_request(format!("Refresh interval must be at least {} seconds", MIN_REFRESH_INTERVAL_SECS)));
+        }
+        Some(interval_secs) => Some(RefreshSchedule::new(interval_secs, unix_now())),
+        None => None,
+    };
+    vault.store(key_id, &record)?;
+
+    Ok(refresh_schedule_response(key_id, &record))
+}
+
+/// Moves the schedule of the key past `now`, whether the refresh of the current slot succeeded or not
+pub fn advance_refresh_schedule(vault: &KeyVault, key_id: &str, now: u64) -> Result<(), ServiceError> {
+    let mut record = vault.load(key_id)?;
+    if let Some(schedule) = record.refresh_schedule.as_mut() {
+        schedule.advance(now);
+        vault.store(key_id, &record)?;
+    }
+    Ok(())
+}
+
+fn refresh_schedule_response(key_id: &str, record: &KeyRecord) -> RefreshScheduleResponse {
+    RefreshScheduleResponse {
+        key_id: key_id.to_string(),
+        epoch: record.epoch,
+        interval_secs: record.refresh_schedule.map(|schedule| schedule.interval_secs),
+        next_refresh_at: record.refresh_schedule.map(|schedule| schedule.next_refresh_at),
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+async fn run_key_refresh(
+    node: &Node,
+    primes: &PrimePool,
+    exec_id: &[u8],
+    key_share: &KeyShare<Secp256k1, SecurityLevel128>,
+    tracer: &mut JobTracer,
+) -> Result<(IncompleteKeyShare<Secp256k1>, AuxInfo<SecurityLevel128>), ServiceError> {
+    let session = node.session(ProtocolKind::KeyRefresh, exec_id)?;
+    let party = round_based::MpcParty::connected(session.delivery::<NonThresholdMsg<Secp256k1, Sha256, SecurityLevel128>>());
+    let pregenerated_primes = primes.take().await;
+
+    println!("Refreshing key share...");
+    let refreshed = cggmp21::key_refresh(ExecutionId::new(exec_id), key_share, pregenerated_primes)
+        .set_progress_tracer(tracer.begin("key-refresh"))
+        .start(&mut OsRng, party)
+        .await
+        .map_err(|e| ServiceError::protocol("Key refresh failed", &e))?;
+    session.finish();
+
+    let DirtyKeyShare { core, aux } = refreshed.into_inner();
+    let core = core.validate().map_err(|e| ServiceError::internal(format!("Refreshed key share is invalid: {}", e.into_error())))?;
+    let aux = aux.validate().map_err(|e| ServiceError::internal(format!("Refreshed aux info is invalid: {}", e.into_error())))?;
+    Ok((core, aux))
+}
+
+async fn run_aux_info_gen(
+    node: &Node,
+    primes: &PrimePool,
+    exec_id: &[u8],
+    tracer: &mut JobTracer,
+) -> Result<AuxInfo<SecurityLevel128>, ServiceError> {
+    let eid = ExecutionId::new(exec_id);
+
+    let session = node.session(ProtocolKind::AuxInfo, exec_id)?;
+    let party = round_based::MpcParty::connected(session.delivery());
+
+    // The session is opened first so messages of faster parties are kept while waiting for primes
+    if primes.available() == 0{
+        println!("Waiting for pregenerated primes...");
+    }
+    let pregenerated_primes = primes.take().await;
+
+    println!("Generating aux info...");
+    let aux_info = cggmp21::aux_info_gen(eid, node.local_party_id, node.n, pregenerated_primes)
+        .enforce_reliable_broadcast(false)
+        .set_progress_tracer(tracer.begin("aux-info"))
+        .start(&mut OsRng, party)
+        .await
+        .map_err(|e| ServiceError::protocol("Aux info generation failed", &e))?;
+    session.finish();
+    println!("Aux info generated...");
+
+    Ok(aux_info)
+}
+
+pub async fn sign_transaction(
+    node: &Node,
+    vault: &KeyVault,
+    presignatures: &PresignaturePool,
+    opts: &SignTransactionReqBody,
+    tracer: &mut JobTracer,
+) -> Result<SignTransactionResponse, ServiceError> {
+    let signing_input = match &opts.typed_data{
+        Some(_) if opts.digest.is_some() || opts.message.is_some() => {
+            return Err(ServiceError::bad_request("Typed data cannot be combined with a digest or a message"));
+        }
+        Some(typed_data) => SigningInput::from_typed_data(typed_data),
+        None => SigningInput::from_hex(opts.digest.as_deref(), opts.message.as_deref(), opts.hash),
+    }.map_err(|e| ServiceError::bad_request(e.to_string()))?;
+
+    let key = load_stealth_key(vault, &opts.key_id, &opts.entry, &opts.viewing_sk, opts.view_tag_version, &opts.viewtag)?;
+    let public_key = key.public_key;
+
+    let signature = sign_with_stealth_key(
+        node, presignatures, opts.exec_id.as_bytes(), key, signing_mode(opts.presignature_id.as_deref(), opts.signers.as_deref())?,
+        signing_input.data_to_sign(), tracer
+    ).await?;
+
+    let digest = signing_input.digest();
+    let signature = RecoverableSignature::from_signature(signature, &public_key, &digest)
+        .map_err(|e| ServiceError::internal(e.to_string()))?;
+
+    Ok(SignTransactionResponse {
+        digest: format!("0x{}", hex::encode(digest)),
+        r: format!("0x{}", hex::encode(signature.r)),
+        s: format!("0x{}", hex::encode(signature.s)),
+        v: signature.v(),
+        y_parity: signature.y_parity as u8,
+        signature: format!("0x{}", hex::encode(signature.to_compact())),
+        der: format!("0x{}", hex::encode(signature.to_der()))
+    })
+}
+
+pub async fn sign_eth_transaction(
+    node: &Node,
+    vault: &KeyVault,
+    presignatures: &PresignaturePool,
+    opts: &SignEthTransactionReqBody,
+    tracer: &mut JobTracer,
+) -> Result<SignEthTransactionResponse, ServiceError> {
+    let key = load_stealth_key(vault, &opts.key_id, &opts.entry, &opts.viewing_sk, opts.view_tag_version, &opts.viewtag)?;
+    let stealth_public_key = key.public_key;
+    let sender = Address::from_public_key(&stealth_public_key);
+    if let Some(stealth_address) = opts.stealth_address && stealth_address != sender{
+        return Err(ServiceError::bad_request(format!("Key {} controls {}, not {}", opts.key_id, sender, stealth_address)));
+    }
+
+    let digest = opts.transaction.signing_hash();
+    let data_to_sign = DataToSign::from_scalar(Scalar::from_be_bytes_mod_order(digest));
+
+    let signature = sign_with_stealth_key(
+        node, presignatures, opts.exec_id.as_bytes(), key, signing_mode(opts.presignature_id.as_deref(), opts.signers.as_deref())?,
+        data_to_sign, tracer
+    ).await?;
+
+    let signature = RecoverableSignature::from_signature(signature, &stealth_public_key, &digest)
+        .map_err(|e| ServiceError::internal(e.to_string()))?;
+    let raw_transaction = opts.transaction.encode_signed(&signature);
+    let tx_hash = Keccak256::digest(&raw_transaction);
+
+    Ok(SignEthTransactionResponse {
+        raw_transaction: format!("0x{}", hex::encode(raw_transaction)),
+        tx_hash: format!("0x{}", hex::encode(tx_hash)),
+        from: sender.to_string()
+    })
+}
+
+pub async fn generate_presignatures(
+    node: &Node,
+    vault: &KeyVault,
+    presignatures: &PresignaturePool,
+    opts: &PresignReqBody,
+    tracer: &mut JobTracer,
+) -> Result<PresignResponse, ServiceError> {
+    if opts.count == 0 || opts.count > MAX_PRESIGNATURES_PER_JOB {
+        return Err(ServiceError::bad_request(format!("Count must be between 1 and {}", MAX_PRESIGNATURES_PER_JOB)));
+    }
+
+    let record = vault.load(&opts.key_id)?;
+    let signers = signer_set(node, &record.key_share, opts.signers.as_deref())?;
+    let aux_info = cached_aux_info(record.aux_info, &opts.key_id)?;
+    let key_share = KeyShare::from_parts((record.key_share, aux_info))
+        .map_err(|e| ServiceError::internal(format!("Cannot complete key share: {}", e)))?;
+    let participants = Participants::new(&signers);
+    let signer_index = participants.protocol_index(node.local_party_id).expect("Signer set contains the local party");
+
+    let mut presignature_ids = Vec::with_capacity(opts.count as usize);
+    for i in 0..opts.count {
+        // Every party derives the same id, so they all consume matching presignatures
+        let id = format!("{}/{}", opts.exec_id, i);
+
+        let session = node.session(ProtocolKind::Presigning, id.as_bytes())?;
+        let party = round_based::MpcParty::connected(session.delivery_among(participants.clone()));
+
+        println!("Generating presignature {}...", id);
+        let presignature = cggmp21::signing(ExecutionId::new(id.as_bytes()), signer_index, &signers, &key_share)
+            .set_progress_tracer(tracer.begin("presigning"))
+            .generate_presignature(&mut OsRng, party)
+            .await
+            .map_err(|e| ServiceError::protocol("Presigning failed", &e))?;
+        session.finish();
+
+        presignatures.insert(id.clone(), &opts.key_id, signers.clone(), presignature)?;
+        presignature_ids.push(id);
+    }
+
+    Ok(PresignResponse {
+        key_id: opts.key_id.clone(),
+        presignature_ids
+    })
+}
+
+pub fn list_presignatures(presignatures: &PresignaturePool, key_id: &str) -> PresignaturesResponse {
+    PresignaturesResponse {
+        key_id: key_id.to_string(),
+        presignatures: presignatures.available(key_id)
+            .into_iter()
+            .map(|(id, expires_in)| AvailablePresignature { id, expires_in_secs: expires_in.as_secs() })
+            .collect()
+    }
+}
+
+// Key of the stealth address a request signs with
+struct StealthKey {
+    key_id: String,
+    record: KeyRecord,
+    b: BigInt<4>,
+    public_key: Point<Secp256k1>,
+}
+
+fn load_stealth_key(
+    vault: &KeyVault,
+    key_id: &str,
+    entry: &String,
+    viewing_sk: &String,
+    view_tag_version: usize,
+    viewtag: &str,
+) -> Result<StealthKey, ServiceError> {
+    let record = vault.load(key_id)?;
+    let b = stealth_tweak(entry, viewing_sk, view_tag_version, viewtag)?;
+    let public_key = MpcCurvy::tweaked_public_key(&record.key_share, b)
+        .map_err(|e| ServiceError::internal(format!("Cannot tweak public key: {}", e)))?;
+
+    Ok(StealthKey { key_id: key_id.to_string(), record, b, public_key })
+}
+
+// How the signers produce a signature
+enum SigningMode<'a> {
+    /// Single round from a presignature, its signers were fixed when it was generated
+    Presignature(&'a str),
+    /// Whole signing protocol between the named signers, or all parties
+    Full(Option<&'a [u16]>),
+}
+
+fn signing_mode<'a>(presignature_id: Option<&'a str>, signers: Option<&'a [u16]>) -> Result<SigningMode<'a>, ServiceError> {
+    match (presignature_id, signers) {
+        (Some(_), Some(_)) => Err(ServiceError::bad_request("Signers of a presignature are fixed when it is generated, do not name them again")),
+        (Some(presignature_id), None) => Ok(SigningMode::Presignature(presignature_id)),
+        (None, signers) => Ok(SigningMode::Full(signers)),
+    }
+}
+
+async fn sign_with_stealth_key(
+    node: &Node,
+    presignatures: &PresignaturePool,
+    exec_id: &[u8],
+    key: StealthKey,
+    mode: SigningMode<'_>,
+    data_to_sign: DataToSign<Secp256k1>,
+    tracer: &mut JobTracer,
+) -> Result<Signature<Secp256k1>, ServiceError> {
+    match mode {
+        SigningMode::Presignature(presignature_id) => {
+            // The presignature is used up once taken, it is kept while a signer is unreachable
+            if let Some(signers) = presignatures.signers(presignature_id) {
+                check_reachable(node, &signers)?;
+            }
+            let (presignature, signers) = presignatures.take(presignature_id, &key.key_id)?;
+            let presignature = MpcCurvy::tweak_presignature(presignature, key.b)
+                .map_err(|e| ServiceError::internal(format!("Cannot tweak presignature: {}", e)))?;
+            run_online_signing(node, exec_id, presignature, &signers, data_to_sign, &key.public_key, tracer).await
+        }
+        SigningMode::Full(signers) => {
+            let signers = signer_set(node, &key.record.key_share, signers)?;
+            let aux_info = cached_aux_info(key.record.aux_info, &key.key_id)?;
+            let key_share = MpcCurvy::update_shares_and_complete(key.record.key_share, key.b, aux_info)
+                .map_err(|e| ServiceError::internal(format!("Cannot complete key share: {}", e)))?;
+            run_signing(node, exec_id, &key_share, &signers, data_to_sign, tracer).await
+        }
+    }
+}
+
+// Recomputes the stealth tweak b from the announcement, after checking the viewtag
+fn stealth_tweak(
+    entry: &String,
+    viewing_sk: &String,
+    view_tag_version: usize,
+    viewtag: &str,
+) -> Result<BigInt<4>, ServiceError> {
+    let g2 = G2Affine::generator();
+
+    let viewing_sk = deserialize_field_element(viewing_sk)
+        .map_err(|e| ServiceError::bad_request(format!("Invalid viewing key: {}", e)))?;
+    let ephemeral_pk = deserialize_affine_point(entry)
+        .map_err(|e| ServiceError::bad_request(format!("Invalid ephemeral public key: {}", e)))?;
+
+    let v_r_product = (ephemeral_pk * viewing_sk).into_affine();
+    let computed_viewtag = compute_viewtag(&v_r_product, view_tag_version)
+        .map_err(|e| ServiceError::bad_request(e.to_string()))?;
+
+    if viewtag != computed_viewtag{
+        return Err(ServiceError::bad_request("Viewtag does not match"));
+    }
+    let ss =  Bn254::pairing(v_r_product, g2).0;
+    Ok(get_first_coordinate(&ss))
+}
+
+// Signer set of a request by keygen index, sorted so every signer numbers the parties the same way
+fn signer_set(node: &Node, key_share: &IncompleteKeyShare<Secp256k1>, signers: Option<&[u16]>) -> Result<Vec<u16>, ServiceError> {
+    let n = key_share.public_shares.len() as u16;
+    let min_signers = key_share.vss_setup.as_ref().map(|vss_setup| vss_setup.min_signers).unwrap_or(n);
+
+    let mut signers = match signers {
+        Some(signers) => signers.to_vec(),
+        None => (0..n).collect(),
+    };
+    signers.sort_unstable();
+
+    if signers.windows(2).any(|pair| pair[0] == pair[1]) {
+        return Err(ServiceError::bad_request("Signer set contains a party twice"));
+    }
+    if let Some(party) = signers.iter().find(|party| **party >= n) {
+        return Err(ServiceError::bad_request(format!("Unknown signer {}, the key is shared by {} parties", party, n)));
+    }
+    if signers.len() < usize::from(min_signers) {
+        return Err(ServiceError::bad_request(format!("The key needs {} signers, {} given", min_signers, signers.len())));
+    }
+    if !signers.contains(&node.local_party_id) {
+        return Err(ServiceError::bad_request(format!("Party {} is not in the signer set", node.local_party_id)));
+    }
+    check_reachable(node, &signers)?;
+    Ok(signers)
+}
+
+// A run with a party that is down would only end with the round timeout
+fn check_reachable(node: &Node, signers: &[u16]) -> Result<(), ServiceError> {
+    let unreachable = node.unreachable(signers);
+    if !unreachable.is_empty() {
+        return Err(ServiceError::unavailable(format!("Signers {:?} are not connected", unreachable)));
+    }
+    Ok(())
+}
+
+// Every party must hold aux info for the key before signing, a party missing it would stall the others
+fn cached_aux_info(aux_info: Option<AuxInfo<SecurityLevel128>>, key_id: &str) -> Result<AuxInfo<SecurityLevel128>, ServiceError> {
+    aux_info.ok_or_else(|| ServiceError {
+        status: StatusCode::CONFLICT,
+        message: format!("Key {} has no aux info yet, run the aux-info job first", key_id),
+    })
+}
+
+async fn run_signing(
+    node: &Node,
+    exec_id: &[u8],
+    key_share: &KeyShare<Secp256k1, SecurityLevel128>,
+    signers: &[u16],
+    data_to_sign: DataToSign<Secp256k1>,
+    tracer: &mut JobTracer,
+) -> Result<Signature<Secp256k1>, ServiceError> {
+    let eid = ExecutionId::new(exec_id);
+    let participants = Participants::new(signers);
+    let signer_index = participants.protocol_index(node.local_party_id).expect("Signer set contains the local party");
+
+    let session = node.session(ProtocolKind::Signing, exec_id)?;
+    let party = round_based::MpcParty::connected(session.delivery_among(participants));
+
+    println!("Signing with parties {:?}...", signers);
+    let signature = cggmp21::signing(eid, signer_index, signers, key_share)
+        .set_progress_tracer(tracer.begin("signing"))
+        .sign(&mut OsRng, party, data_to_sign)
+        .await
+        .map_err(|e| ServiceError::protocol("Signing failed", &e))?;
+    session.finish();
+    println!("Signed!");
+
+    Ok(signature)
+}
+
+
+/// Partial signature broadcast in the online signing round
+#[derive(Clone, Serialize, Deserialize)]
+struct OnlineSigningMsg(PartialSignature<Secp256k1>);
+
+impl ProtocolMessage for OnlineSigningMsg {
+    fn round(&self) -> u16 {
+        1
+    }
+}
+
+// Online phase of presignature based signing: a single round of partial signatures
+async fn run_online_signing(
+    node: &Node,
+    exec_id: &[u8],
+    presignature: Presignature<Secp256k1>,
+    signers: &[u16],
+    data_to_sign: DataToSign<Secp256k1>,
+    public_key: &Point<Secp256k1>,
+    tracer: &mut JobTracer,
+) -> Result<Signature<Secp256k1>, ServiceError> {
+    let participants = Participants::new(signers);
+    let session = node.session(ProtocolKind::OnlineSigning, exec_id)?;
+    // Only the signers of the presignature take part, the other parties never open the session
+    let (mut incoming, mut outgoing) = session.delivery_among::<OnlineSigningMsg>(participants.clone());
+    tracer.begin("online-signing");
+
+    let partial_signature = presignature.issue_partial_signature(data_to_sign);
+    outgoing.send(Outgoing::broadcast(OnlineSigningMsg(partial_signature.clone()))).await
+        .map_err(|e| ServiceError::internal(format!("Cannot send partial signature: {}", e)))?;
+
+    let mut partial_signatures = BTreeMap::from([(node.local_party_id, partial_signature)]);
+    while partial_signatures.len() < signers.len() {
+        match incoming.next().await {
+            Some(Ok(msg)) => {
+                // Senders are numbered by their position in the signer set
+                let party = participants.party(msg.sender).expect("Sender is a signer");
+                partial_signatures.insert(party, msg.msg.0);
+            }
+            Some(Err(e)) => return Err(ServiceError::internal(format!("Cannot receive partial signature: {}", e))),
+            None => return Err(ServiceError::internal("Session closed before every partial signature arrived")),
+        }
+    }
+    session.finish();
+
+    let partial_signatures: Vec<_> = partial_signatures.into_values().collect();
+    let signature = PartialSignature::combine(&partial_signatures)
+        .ok_or_else(|| ServiceError::internal("No partial signature to combine"))?;
+    signature.verify(public_key, &data_to_sign)
+        .map_err(|_| ServiceError::internal("Combined signature is invalid"))?;
+
+    Ok(signature)
+}
\ No newline at end of file